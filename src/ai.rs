@@ -0,0 +1,288 @@
+//! Computer-controlled rivals. Each grid step a rival picks its heading from
+//! the state of the simulation alone, so the same game always plays out the
+//! same way.
+
+use std::{cmp::Reverse, collections::BinaryHeap, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grid::GridPos,
+    sim::{CellKind, Direction, SnakeSim},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    /// Heads straight for the nearest food, only dodging what is right ahead
+    Greedy,
+    /// Follows the shortest path to a food when there is room left around it
+    /// once there, otherwise moves towards the most room
+    #[default]
+    Pathfinding,
+    /// Only goes for food it can eat and still reach its own tail afterwards,
+    /// otherwise chases its tail
+    Survival,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Greedy, Difficulty::Pathfinding, Difficulty::Survival];
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Difficulty::Greedy => "greedy",
+            Difficulty::Pathfinding => "pathfinding",
+            Difficulty::Survival => "survival",
+        })
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "greedy" => Ok(Difficulty::Greedy),
+            "pathfinding" => Ok(Difficulty::Pathfinding),
+            "survival" => Ok(Difficulty::Survival),
+            other => Err(format!("unknown difficulty {other:?}, expected greedy, pathfinding or survival")),
+        }
+    }
+}
+
+/// Steps ahead a rival keeps clear of the moving obstacles, they kill any
+/// segment they run into
+const HAZARD_LOOKAHEAD: u32 = 3;
+
+/// Heading for `snake` to take on the next step
+pub fn choose_direction(sim: &SnakeSim, snake: usize, difficulty: Difficulty) -> Direction {
+    let view = View::new(sim, snake);
+    let moves = view.moves();
+    let Some(&(fallback, _)) = moves.first() else {
+        return sim.snake(snake).direction();
+    };
+    let choice = match difficulty {
+        Difficulty::Greedy => moves.iter()
+            .min_by_key(|&&(_, next)| view.food_distance(next))
+            .map(|&(direction, _)| direction),
+        Difficulty::Pathfinding => view.path_to_food(|body| view.room(body) >= body.len())
+            .or_else(|| view.most_room(&moves)),
+        Difficulty::Survival => view.path_to_food(|body| view.reaches_tail(body))
+            .or_else(|| view.path_to_tail())
+            .or_else(|| view.most_room(&moves)),
+    };
+    choice.unwrap_or(fallback)
+}
+
+/// The arena as one snake sees it
+struct View<'a> {
+    sim: &'a SnakeSim,
+    snake: usize,
+    /// Cells closed to it, x-major: walls, rising obstacles, the cells moving
+    /// ones cover soon and the other snakes. Its own body is kept apart.
+    closed: Vec<bool>,
+    /// Cells the other heads can step onto next
+    contested: Vec<GridPos>,
+    /// Manhattan distance is a lower bound on the path length
+    flat: bool,
+}
+
+impl<'a> View<'a> {
+    fn new(sim: &'a SnakeSim, snake: usize) -> Self {
+        let level = sim.level();
+        let hazards: Vec<GridPos> = (1..=HAZARD_LOOKAHEAD).flat_map(|steps| sim.hazards_during(steps)).collect();
+        let mut closed: Vec<bool> = sim.cells()
+            .map(|(pos, kind)| kind.is_blocking() || matches!(kind, CellKind::Rising(_)) || hazards.contains(&pos))
+            .collect();
+        let mut contested = Vec::new();
+        for (i, other) in sim.snakes().iter().enumerate() {
+            if i == snake || !other.is_alive() {
+                continue;
+            }
+            for &pos in other.segments() {
+                closed[pos.x as usize * sim.height() + pos.z as usize] = true;
+            }
+            contested.extend(level.neighbors(other.head()));
+        }
+        let flat = !level.wrap && level.cube_net().is_none() && level.layers == 1 && level.portals.is_empty();
+        Self { sim, snake, closed, contested, flat }
+    }
+
+    fn index(&self, pos: GridPos) -> usize {
+        pos.x as usize * self.sim.height() + pos.z as usize
+    }
+
+    /// Open to a snake whose segments are `body`, its tail moves out of the way
+    fn open(&self, pos: GridPos, body: &[GridPos]) -> bool {
+        !self.closed[self.index(pos)] && !body[..body.len() - 1].contains(&pos)
+    }
+
+    fn body(&self) -> Vec<GridPos> {
+        self.sim.snake(self.snake).segments().iter().copied().collect()
+    }
+
+    /// The cell a step from `pos` heading `direction` really leads to, a belt
+    /// under `pos` pushes along its own direction unless that turns back
+    fn step(&self, pos: GridPos, direction: Direction) -> Option<GridPos> {
+        let direction = match self.sim.cell(pos) {
+            CellKind::Conveyor(push) if push != direction.opposite() => push,
+            _ => direction,
+        };
+        self.sim.step(pos, direction).map(|(next, _)| next)
+    }
+
+    /// Steps that do not end the game right away with the cells they lead to.
+    /// Cells another head could take too are left out unless nothing else is
+    /// open, meeting head-on ends both snakes.
+    fn moves(&self) -> Vec<(Direction, GridPos)> {
+        let me = self.sim.snake(self.snake);
+        let body = self.body();
+        let moves: Vec<(Direction, GridPos)> = Direction::ALL.into_iter()
+            .filter(|&direction| direction != me.direction().opposite())
+            .filter_map(|direction| self.step(me.head(), direction).map(|next| (direction, next)))
+            .filter(|&(_, next)| self.open(next, &body))
+            .collect();
+        let uncontested: Vec<(Direction, GridPos)> = moves.iter().copied()
+            .filter(|(_, next)| !self.contested.contains(next))
+            .collect();
+        if uncontested.is_empty() { moves } else { uncontested }
+    }
+
+    fn food_distance(&self, pos: GridPos) -> i32 {
+        self.sim.foods().iter()
+            .map(|food| (food.pos.x - pos.x).abs() + (food.pos.z - pos.z).abs())
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// First step of the shortest path from the head to a cell `goal` accepts
+    /// and the body once there, found with A*. The path starts with one of
+    /// the `moves`.
+    fn path(&self, goal: impl Fn(GridPos) -> bool, heuristic: impl Fn(GridPos) -> i32) -> Option<(Direction, Vec<GridPos>)> {
+        let body = self.body();
+        let head = body[0];
+        let first_steps: Vec<GridPos> = self.moves().into_iter().map(|(_, next)| next).collect();
+        let cells = self.sim.width() * self.sim.height();
+        let mut cost = vec![i32::MAX; cells];
+        let mut came_from: Vec<Option<(GridPos, Direction)>> = vec![None; cells];
+        let mut queue = BinaryHeap::new();
+        cost[self.index(head)] = 0;
+        queue.push(Reverse((heuristic(head), 0, head.x, head.z)));
+        while let Some(Reverse((_, steps, x, z))) = queue.pop() {
+            let pos = GridPos::new(x, z);
+            if steps > cost[self.index(pos)] {
+                continue;
+            }
+            if pos != head && goal(pos) {
+                return Some(self.walk_back(pos, &came_from, &body));
+            }
+            for direction in Direction::ALL {
+                let Some(next) = self.step(pos, direction) else { continue };
+                if pos == head && !first_steps.contains(&next) {
+                    continue;
+                }
+                let i = self.index(next);
+                if steps + 1 < cost[i] && self.open(next, &body) {
+                    cost[i] = steps + 1;
+                    came_from[i] = Some((pos, direction));
+                    queue.push(Reverse((steps + 1 + heuristic(next), steps + 1, next.x, next.z)));
+                }
+            }
+        }
+        None
+    }
+
+    /// The first direction of the path to `end` and the body after walking
+    /// it, one segment longer for the food waiting there
+    fn walk_back(&self, end: GridPos, came_from: &[Option<(GridPos, Direction)>], body: &[GridPos]) -> (Direction, Vec<GridPos>) {
+        let mut path = vec![end];
+        let mut first = None;
+        let mut pos = end;
+        while let Some((prev, direction)) = came_from[self.index(pos)] {
+            first = Some(direction);
+            if prev == body[0] {
+                break;
+            }
+            path.push(prev);
+            pos = prev;
+        }
+        path.extend(body.iter().copied());
+        path.truncate(body.len() + 1);
+        (first.unwrap_or(self.sim.snake(self.snake).direction()), path)
+    }
+
+    /// Shortest way to a food that leaves the snake in a state `safe` accepts
+    fn path_to_food(&self, safe: impl Fn(&[GridPos]) -> bool) -> Option<Direction> {
+        let foods: Vec<GridPos> = self.sim.foods().iter().map(|food| food.pos).collect();
+        let (direction, body) = self.path(|pos| foods.contains(&pos), |pos| {
+            if self.flat { self.food_distance(pos) } else { 0 }
+        })?;
+        safe(&body).then_some(direction)
+    }
+
+    fn path_to_tail(&self) -> Option<Direction> {
+        let body = self.body();
+        let tail = *body.last()?;
+        if body.len() < 3 {
+            return None;
+        }
+        let distance = |pos: GridPos| if self.flat { (tail.x - pos.x).abs() + (tail.z - pos.z).abs() } else { 0 };
+        self.path(|pos| pos == tail, distance).map(|(direction, _)| direction)
+    }
+
+    /// Cells a snake of `body` can still reach from its head
+    fn reachable(&self, body: &[GridPos]) -> Vec<bool> {
+        self.sim.level().flood_fill(body[0], |pos| pos == body[0] || self.open(pos, body))
+    }
+
+    fn room(&self, body: &[GridPos]) -> usize {
+        self.reachable(body).into_iter().filter(|&reached| reached).count()
+    }
+
+    fn reaches_tail(&self, body: &[GridPos]) -> bool {
+        let tail = body[body.len() - 1];
+        body.len() < 3 || self.reachable(body)[self.index(tail)]
+    }
+
+    /// The step with the most free floor behind it
+    fn most_room(&self, moves: &[(Direction, GridPos)]) -> Option<Direction> {
+        let body = self.body();
+        // the first of equally good moves
+        moves.iter().rev()
+            .max_by_key(|&&(_, next)| {
+                let mut moved = vec![next];
+                moved.extend(&body[..body.len() - 1]);
+                self.room(&moved)
+            })
+            .map(|&(direction, _)| direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        level::{Conveyor, Level, ObstacleSchedule},
+        rng::GameRng,
+    };
+
+    #[test]
+    fn rivals_step_where_belts_push_them() {
+        // the belt under the head turns going up into a step right, into the
+        // wall, so only going left is safe even with the food straight up
+        let mut level = Level::open(10, 10);
+        level.obstacles = ObstacleSchedule::Off;
+        level.food_count = 0;
+        level.conveyors.push(Conveyor { at: level.start, direction: Direction::Right });
+        level.walls.push(level.start + GridPos::new(1, 0));
+        let mut sim = SnakeSim::new(&level);
+        sim.place_food(level.start + GridPos::new(0, -3));
+        for difficulty in [Difficulty::Greedy, Difficulty::Pathfinding, Difficulty::Survival] {
+            assert_eq!(choose_direction(&sim, 0, difficulty), Direction::Left, "{difficulty}");
+        }
+        sim.turn(Direction::Left);
+        sim.tick(&mut GameRng::new(0));
+        assert!(sim.is_alive());
+    }
+}
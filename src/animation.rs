@@ -1,8 +1,9 @@
-use std::{ops::{Add, Mul, Sub}, time::Duration};
-use crate::{game_flow::BodyIndex, utils::*, CubeState, Snake, FOOD_COLOR};
+use std::time::Duration;
+use crate::{game_flow::BodyIndex, utils::*, Snake};
 use bevy::prelude::*;
+use snake_3d::sim::{OBSTACLE_MOVE_TIME, OBSTACLE_WARN_TIME};
 
-use crate::{GameState, GlobalAssets};
+use crate::GlobalAssets;
 
 pub struct AnimationPlugin;
 impl Plugin for AnimationPlugin {
@@ -37,13 +38,16 @@ pub struct FoodAnimation {
     pub duration: f32,
     pub elapsed: f32,
     pub amplitude: f32,
+    /// Resting position, the food bobs along its local up from here
+    pub origin: Vec3,
 }
-impl Default for FoodAnimation {
-    fn default() -> Self {
+impl FoodAnimation {
+    pub fn new(origin: Vec3) -> Self {
         Self {
             duration: 2.0,
             elapsed: 0.0,
             amplitude: 0.5, // control how much the item moves up and down
+            origin,
         }
     }
 }
@@ -57,9 +61,9 @@ fn update_food_animation(
         effect.elapsed += time.delta_secs();
         let cycle_pos = effect.elapsed / effect.duration;
         let ease_val = ease_in_out_sine(cycle_pos);
-        transform.rotate(Quat::from_rotation_y(time.delta_secs()));
+        transform.rotate_local_y(time.delta_secs());
         // move up down a little bit
-        transform.translation.y = ease_val * effect.amplitude;
+        transform.translation = effect.origin + transform.up() * ease_val * effect.amplitude;
     }
 }
 
@@ -91,9 +95,9 @@ pub struct DeactiveCubeAnimation {
 impl DeactiveCubeAnimation {
     pub fn new(from: Vec3, to: Vec3) -> Self {
         Self {
-            warn_duration: 1.5,
+            warn_duration: OBSTACLE_WARN_TIME,
             warn_elapsed: 0.0,
-            duration: 0.5,
+            duration: OBSTACLE_MOVE_TIME,
             elapsed: 0.0,
             from,
             to,
@@ -104,9 +108,9 @@ impl DeactiveCubeAnimation {
 fn update_deactive_cube_animation (
     time:           Res<Time>,
     game_assets:    Res<GlobalAssets>,
-    mut query:      Query<(&mut Transform, &mut CubeState, &mut MeshMaterial3d<StandardMaterial> , &mut DeactiveCubeAnimation)>,
+    mut query:      Query<(&mut Transform, &mut MeshMaterial3d<StandardMaterial> , &mut DeactiveCubeAnimation)>,
 ) {
-    for (mut transform, mut cube, mut mat, mut anim) in query.iter_mut() {
+    for (mut transform, mut mat, mut anim) in query.iter_mut() {
         // Phase 1
         if anim.warn_elapsed < anim.warn_duration {
             anim.warn_elapsed += time.delta_secs();
@@ -134,7 +138,6 @@ fn update_deactive_cube_animation (
                 //     *material = game_assets.map_cube_mat_emission.clone();
                 // }
                 // *mat = MeshMaterial3d(game_assets.map_cube_mat_emission.clone());
-                cube.walkable = false;
                 // commands.entity(entity).insert(MeshMaterial3d(game_assets.map_cube_mat_emission.clone()));
             }
         }
@@ -156,9 +159,9 @@ pub struct ActiveCubeAnimation {
 impl ActiveCubeAnimation {
     pub fn new(from: Vec3, to: Vec3) -> Self {
         Self {
-            warn_duration: 1.5,
+            warn_duration: OBSTACLE_WARN_TIME,
             warn_elapsed: 0.0,
-            duration: 0.5,
+            duration: OBSTACLE_MOVE_TIME,
             elapsed: 0.0,
             from,
             to,
@@ -171,10 +174,10 @@ fn update_active_cube_animation (
     time:           Res<Time>,
     game_assets:    Res<GlobalAssets>,
     mut query:      Query<
-        (Entity, &mut Transform, &mut CubeState, &mut MeshMaterial3d<StandardMaterial>, &mut ActiveCubeAnimation)
+        (Entity, &mut Transform, &mut MeshMaterial3d<StandardMaterial>, &mut ActiveCubeAnimation)
     >,
 ) {
-    for (entity, mut transform, mut cube, mut mat, mut anim) in query.iter_mut() {
+    for (entity, mut transform, mut mat, mut anim) in query.iter_mut() {
         // Phase 1
         if anim.warn_elapsed < anim.warn_duration {
             anim.warn_elapsed += time.delta_secs();
@@ -188,7 +191,6 @@ fn update_active_cube_animation (
                 transform.translation = anim.to;
                 commands.entity(entity).remove::<ActiveCubeAnimation>();
                 *mat = MeshMaterial3d(game_assets.map_cube_mat.clone());
-                cube.walkable = true;
             }
         }
     }
@@ -211,17 +213,18 @@ fn update_dead_effect (
     time:           Res<Time>,
     game_assets:    Res<GlobalAssets>,
     mut query:      Query<
-        (Entity, &mut Transform, &mut MeshMaterial3d<StandardMaterial>, &BodyIndex, &mut DeadEffect)
+        (Entity, &mut MeshMaterial3d<StandardMaterial>, &BodyIndex, &mut DeadEffect)
     >,
     player_query:   Query<&Snake>,
 ) {
-    for (entity, mut transform, mut mat, body_index, mut anim) in query.iter_mut() {
+    for (entity, mut mat, body_index, mut anim) in query.iter_mut() {
         anim.timer.tick(Duration::from_secs_f32(time.delta_secs()));
         if anim.timer.just_finished() {
             *mat = MeshMaterial3d(game_assets.red_mat.clone());
             commands.entity(entity).remove::<DeadEffect>();
 
-            if player_query.single().bodies.len() == body_index.0 + 1 {
+            // the longest snake's tail goes last
+            if player_query.iter().map(|snake| snake.bodies.len()).max() == Some(body_index.0 + 1) {
                 commands.spawn((
                     AudioPlayer::<AudioSource>(game_assets.game_over.clone()),
                     PlaybackSettings::DESPAWN,
@@ -0,0 +1,50 @@
+//! Replays every recorded game in a directory without rendering and checks
+//! that each one still ends with its recorded score.
+//!
+//! `cargo run --bin replay_check -- [replays dir]`
+
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use snake_3d::replay::Replay;
+
+fn main() -> ExitCode {
+    let dir = std::env::args().nth(1).map_or_else(|| PathBuf::from("replays"), PathBuf::from);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("cannot read {}: {err}", dir.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "ron"))
+        .collect();
+    paths.sort();
+
+    let mut failed = 0;
+    for path in paths.iter() {
+        let replay = match Replay::load(path) {
+            Ok(replay) => replay,
+            Err(err) => {
+                println!("ERROR {}: {err}", path.display());
+                failed += 1;
+                continue;
+            }
+        };
+        let sim = replay.run();
+        if sim.score() == replay.final_score && sim.ticks() == replay.final_tick {
+            println!("ok    {} score {}", path.display(), sim.score());
+        } else {
+            println!(
+                "FAIL  {} score {} (recorded {}), ticks {} (recorded {})",
+                path.display(), sim.score(), replay.final_score, sim.ticks(), replay.final_tick,
+            );
+            failed += 1;
+        }
+    }
+
+    println!("{} replays, {} failed", paths.len(), failed);
+    if failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
@@ -1,10 +1,11 @@
-use bevy::{input::mouse::AccumulatedMouseMotion, prelude::*};
+use bevy::{prelude::*, render::camera::Viewport, window::PrimaryWindow};
 
 pub struct CameraPlugin;
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Update, on_follow_target_added)
+            .add_event::<CameraJump>()
+            .add_systems(Update, (on_follow_target_added, split_viewports))
             .add_systems(Update, (
                 smooth_follow, 
                 update_camera,
@@ -12,8 +13,32 @@ impl Plugin for CameraPlugin {
             ;
     }
 }
+/// Followed by the `TopdownCamera` of the same view
+#[derive(Component, Default)]
+pub struct CameraFollowTarget(pub usize);
+
+/// Orientation of the ground under the target, the camera turns so that
+/// the ground's up and its `Z` stand where world `Y` and `Z` are on a flat arena
 #[derive(Component)]
-pub struct CameraFollowTarget;
+pub struct CameraFrame(pub Quat);
+
+impl CameraFrame {
+    /// Frame of ground facing `normal`, with `down` towards the bottom of the screen
+    pub fn new(normal: Vec3, down: Vec3) -> Self {
+        Self(Quat::from_mat3(&Mat3::from_cols(normal.cross(down), normal, down)))
+    }
+}
+
+/// Rate at which the camera turns towards a new `CameraFrame`
+const FRAME_TURN_SPEED: f32 = 4.0;
+
+/// The target was moved in one go, across the seam of a wrapping arena.
+/// The camera moves along instead of sweeping over the whole map.
+#[derive(Event)]
+pub struct CameraJump {
+    pub view: usize,
+    pub offset: Vec3,
+}
 
 #[derive(Debug, Component)]
 pub struct TopdownCamera {
@@ -22,6 +47,10 @@ pub struct TopdownCamera {
     pub smooth_time: f32,
     pub pos: Vec3,
     pub quat: Quat,
+    /// Current turn of offset and view, eased towards the target's `CameraFrame`
+    pub frame: Quat,
+    /// Side by side views split the window, one per camera
+    pub view: usize,
 }
 
 impl TopdownCamera {
@@ -32,6 +61,8 @@ impl TopdownCamera {
             smooth_time: 0.3,
             pos: offset,
             quat: Quat::IDENTITY,
+            frame: Quat::IDENTITY,
+            view: 0,
         }
     }
 }
@@ -45,37 +76,50 @@ impl Default for TopdownCamera {
             smooth_time: 0.3,
             pos: offset,
             quat: Quat::from_rotation_y(-std::f32::consts::FRAC_PI_4),
+            frame: Quat::IDENTITY,
+            view: 0,
         }
     }
 }
 
 
 fn on_follow_target_added(
-    player_q: Query<&Transform, (Added<CameraFollowTarget>, Without<TopdownCamera>)>,
+    player_q: Query<(&Transform, &CameraFollowTarget), (Added<CameraFollowTarget>, Without<TopdownCamera>)>,
     mut camera_q: Query<(&mut Transform, &mut TopdownCamera), (With<TopdownCamera>, Without<CameraFollowTarget>)>,
 ) {
-    if let (
-        Ok((mut camera_transform, mut topdown_camera)), 
-        Ok(player_transform)
-    ) = (camera_q.get_single_mut(), player_q.get_single()) {
+    for (player_transform, target) in player_q.iter() {
+        let Some((mut camera_transform, mut topdown_camera)) = camera_q.iter_mut()
+            .find(|(_, camera)| camera.view == target.0) else {
+            continue;
+        };
         camera_transform.translation = player_transform.translation + topdown_camera.offset;
         camera_transform.rotation = camera_transform.looking_at(player_transform.translation, Vec3::Y).rotation;
 
         topdown_camera.pos = camera_transform.translation;
         topdown_camera.quat = camera_transform.rotation;
+        topdown_camera.frame = Quat::IDENTITY;
     }
 }
 
 fn smooth_follow(
     time: Res<Time>,
-    target_query: Query<&Transform, With<CameraFollowTarget>>,
+    mut jumps: EventReader<CameraJump>,
+    target_query: Query<(&Transform, &CameraFollowTarget, Option<&CameraFrame>)>,
     mut camera_query: Query<&mut TopdownCamera, (With<TopdownCamera>, Without<CameraFollowTarget>)>,
 ) {
-    if let (
-        Ok(target), 
-        Ok(mut topdown_camera)
-    ) = (target_query.get_single(), camera_query.get_single_mut()) {
-        let target_position = target.translation + topdown_camera.offset;
+    let jumps: Vec<&CameraJump> = jumps.read().collect();
+    for mut topdown_camera in camera_query.iter_mut() {
+        let Some((target, _, frame)) = target_query.iter().find(|(_, target, _)| target.0 == topdown_camera.view) else {
+            continue;
+        };
+        let view = topdown_camera.view;
+        for jump in jumps.iter().filter(|jump| jump.view == view) {
+            topdown_camera.pos += jump.offset;
+        }
+        let frame = frame.map_or(Quat::IDENTITY, |frame| frame.0);
+        let turn = 1.0 - (-FRAME_TURN_SPEED * time.delta_secs()).exp();
+        topdown_camera.frame = topdown_camera.frame.slerp(frame, turn);
+        let target_position = target.translation + topdown_camera.frame * topdown_camera.offset;
         let (new_pos, new_vel) = smooth_damp(
             topdown_camera.pos,
             target_position,
@@ -92,9 +136,35 @@ fn smooth_follow(
 fn update_camera(
     mut camera_query: Query<(&mut Transform, &TopdownCamera), With<TopdownCamera>>,
 ) {
-    if let Ok((mut camera_transform, topdown_camera)) = camera_query.get_single_mut() {
+    for (mut camera_transform, topdown_camera) in camera_query.iter_mut() {
         camera_transform.translation = topdown_camera.pos;
-        camera_transform.rotation = topdown_camera.quat;
+        camera_transform.rotation = topdown_camera.frame * topdown_camera.quat;
+    }
+}
+
+/// Give each view an even slice of the window, a single one has all of it
+fn split_viewports(
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut cameras: Query<(&mut Camera, &TopdownCamera)>,
+) {
+    let Ok(window) = window.get_single() else {
+        return;
+    };
+    let views = cameras.iter().count() as u32;
+    let size = window.physical_size();
+    for (mut camera, topdown_camera) in cameras.iter_mut() {
+        let slice = (views > 1).then(|| (
+            UVec2::new(topdown_camera.view as u32 * size.x / views, 0),
+            UVec2::new((size.x / views).max(1), size.y.max(1)),
+        ));
+        let current = camera.viewport.as_ref().map(|viewport| (viewport.physical_position, viewport.physical_size));
+        if current != slice {
+            camera.viewport = slice.map(|(physical_position, physical_size)| Viewport {
+                physical_position,
+                physical_size,
+                ..default()
+            });
+        }
     }
 }
 
@@ -0,0 +1,389 @@
+use bevy::{color::palettes::css::WHITE, prelude::*, window::PrimaryWindow};
+use snake_3d::{grid::GridPos, level::Level};
+
+use crate::{
+    camera::{CameraFollowTarget, TopdownCamera},
+    input::{Action, ActionState},
+    levels::ActiveLevel,
+    menu::spawn_button,
+    player::Snake,
+    spawn_cube, spawn_grid, CubeState, GameState, GlobalAssets, MapState,
+};
+
+/// Where the editor saves and loads, playable with `--level levels/custom.level.ron`
+const EDITOR_FILE: &str = "assets/levels/custom.level.ron";
+/// Cells per second the view pans with the turn actions
+const PAN_SPEED: f32 = 10.0;
+/// Top of the floor cubes, where clicks land
+const FLOOR_Y: f32 = -0.5;
+
+pub struct EditorPlugin;
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(OnEnter(GameState::Editor), setup_editor)
+            .add_systems(Update, (
+                editor_buttons,
+                pan_view,
+                paint_cells,
+                refresh_preview.after(editor_buttons).after(paint_cells),
+                highlight_tool.after(editor_buttons),
+            ).run_if(in_state(GameState::Editor)))
+            .add_systems(OnExit(GameState::Editor), cleanup_editor)
+            .add_systems(OnTransition { exited: GameState::Editor, entered: GameState::Menu }, cleanup_preview_world);
+    }
+}
+
+/// The layout being edited
+#[derive(Resource)]
+struct Editor {
+    level: Level,
+    tool: Tool,
+    status: String,
+    /// Cells edited since the preview was updated, `None` rebuilds all of it
+    edited: Option<Vec<GridPos>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Wall,
+    Hole,
+    /// Click the start again to turn the starting direction
+    Start,
+    /// Food lying there when the game starts
+    Food,
+    /// A cell new food appears on
+    Spawner,
+}
+
+#[derive(Component, Clone, Copy)]
+enum EditorButton {
+    Tool(Tool),
+    Save,
+    Load,
+    Play,
+    Back,
+}
+
+#[derive(Component)]
+struct EditorUi;
+
+#[derive(Component)]
+struct StatusText;
+
+/// Camera target panned over the arena
+#[derive(Component)]
+struct EditorView;
+
+/// Food and heading previews, rebuilt with the grid
+#[derive(Component)]
+struct EditorMarker;
+
+fn setup_editor(
+    mut commands:   Commands,
+    level:          Res<ActiveLevel>,
+) {
+    let level = level.0.clone();
+    commands.spawn((
+        EditorView,
+        CameraFollowTarget::default(),
+        Transform::from_translation(GridPos::new(level.width as i32 / 2, level.height as i32 / 2).to_world()),
+        GlobalTransform::default(),
+    ));
+
+    commands
+        .spawn((
+            EditorUi,
+            Node {
+                width: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(8.),
+                padding: UiRect::all(Val::Px(10.)),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(Node {
+                    column_gap: Val::Px(8.),
+                    row_gap: Val::Px(8.),
+                    flex_wrap: FlexWrap::Wrap,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                })
+                .with_children(|parent| {
+                    spawn_button(parent, "Wall", EditorButton::Tool(Tool::Wall));
+                    spawn_button(parent, "Hole", EditorButton::Tool(Tool::Hole));
+                    spawn_button(parent, "Start", EditorButton::Tool(Tool::Start));
+                    spawn_button(parent, "Food", EditorButton::Tool(Tool::Food));
+                    spawn_button(parent, "Spawner", EditorButton::Tool(Tool::Spawner));
+                    spawn_button(parent, "Save", EditorButton::Save);
+                    spawn_button(parent, "Load", EditorButton::Load);
+                    spawn_button(parent, "Play", EditorButton::Play);
+                    spawn_button(parent, "Back", EditorButton::Back);
+                });
+            parent.spawn((
+                StatusText,
+                Text::new(""),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+            ));
+        });
+
+    commands.insert_resource(Editor {
+        level,
+        tool: Tool::Wall,
+        status: "click cells to edit, move the view with the turn keys".to_string(),
+        edited: None,
+    });
+}
+
+fn editor_buttons(
+    mut commands:   Commands,
+    game_assets:    Res<GlobalAssets>,
+    mut editor:     ResMut<Editor>,
+    mut next_state: ResMut<NextState<GameState>>,
+    interactions:   Query<(&Interaction, &EditorButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        commands.spawn((
+            AudioPlayer::<AudioSource>(game_assets.button_click.clone()),
+            PlaybackSettings::DESPAWN,
+        ));
+        match *button {
+            EditorButton::Tool(tool) => editor.tool = tool,
+            EditorButton::Save => {
+                if editor.level.name.is_empty() {
+                    editor.level.name = "Custom".to_string();
+                }
+                editor.status = match editor.level.save(EDITOR_FILE) {
+                    Ok(()) => format!("saved to {EDITOR_FILE}"),
+                    Err(err) => err.to_string(),
+                };
+            }
+            EditorButton::Load => {
+                match Level::load(EDITOR_FILE) {
+                    Ok(level) => {
+                        editor.level = level;
+                        editor.edited = None;
+                        editor.status = format!("loaded {EDITOR_FILE}");
+                    }
+                    Err(err) => editor.status = err.to_string(),
+                }
+            }
+            EditorButton::Play => {
+                match editor.level.validate() {
+                    Ok(()) => {
+                        commands.insert_resource(ActiveLevel(editor.level.clone()));
+                        next_state.set(GameState::InGame);
+                    }
+                    Err(err) => editor.status = err.to_string(),
+                }
+            }
+            EditorButton::Back => next_state.set(GameState::Menu),
+        }
+    }
+}
+
+fn highlight_tool(
+    editor:         Res<Editor>,
+    mut buttons:    Query<(&EditorButton, &Interaction, &mut BorderColor)>,
+) {
+    for (button, interaction, mut color) in buttons.iter_mut() {
+        let selected = matches!(button, EditorButton::Tool(tool) if *tool == editor.tool);
+        let alpha = if selected || *interaction != Interaction::None { 1.0 } else { 0.0 };
+        color.set_if_neq(WHITE.with_alpha(alpha).into());
+    }
+}
+
+fn pan_view(
+    time:       Res<Time>,
+    actions:    Res<ActionState>,
+    editor:     Res<Editor>,
+    mut view:   Query<&mut Transform, With<EditorView>>,
+) {
+    let Ok(mut transform) = view.get_single_mut() else {
+        return;
+    };
+    let mut direction = Vec3::ZERO;
+    for action in Action::ALL_TURNS {
+        if let Some(turn) = action.direction().filter(|_| actions.pressed(action)) {
+            direction += turn.norm();
+        }
+    }
+    let max = Vec3::new(editor.level.width as f32 - 1.0, 0.0, editor.level.height as f32 - 1.0);
+    transform.translation = (transform.translation + direction * PAN_SPEED * time.delta_secs()).clamp(Vec3::ZERO, max);
+}
+
+/// Apply the current tool to the cell under a left click
+fn paint_cells(
+    mouse:      Res<ButtonInput<MouseButton>>,
+    window:     Query<&Window, With<PrimaryWindow>>,
+    camera:     Query<(&Camera, &GlobalTransform), With<TopdownCamera>>,
+    ui:         Query<&Interaction, With<Button>>,
+    mut editor: ResMut<Editor>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || ui.iter().any(|i| *i != Interaction::None) {
+        return;
+    }
+    let (Ok(window), Ok((camera, camera_transform))) = (window.get_single(), camera.get_single()) else {
+        return;
+    };
+    let Some(ray) = window.cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok()) else {
+        return;
+    };
+    let Some(distance) = ray.intersect_plane(Vec3::Y * FLOOR_Y, InfinitePlane3d::new(Vec3::Y)) else {
+        return;
+    };
+    let pos = GridPos::from_world(ray.get_point(distance));
+    if !editor.level.contains(pos) {
+        return;
+    }
+    let tool = editor.tool;
+    edit_cell(&mut editor.level, tool, pos);
+    if let Some(edited) = editor.edited.as_mut() {
+        edited.push(pos);
+    }
+}
+
+fn edit_cell(level: &mut Level, tool: Tool, pos: GridPos) {
+    if tool == Tool::Start {
+        if level.start == pos {
+            level.direction = level.direction.clockwise();
+        } else {
+            level.start = pos;
+        }
+    } else if pos == level.start {
+        // the start stays open floor
+        return;
+    }
+    let toggled = match tool {
+        Tool::Wall => Some(&mut level.walls),
+        Tool::Hole => Some(&mut level.holes),
+        Tool::Food => Some(&mut level.foods),
+        Tool::Spawner => Some(&mut level.spawners),
+        Tool::Start => None,
+    };
+    let added = match toggled {
+        Some(cells) if cells.contains(&pos) => {
+            cells.retain(|&c| c != pos);
+            false
+        }
+        Some(cells) => {
+            cells.push(pos);
+            true
+        }
+        None => true,
+    };
+    // a cell holds one thing at a time
+    if added {
+        let layers = [
+            (Tool::Wall, &mut level.walls),
+            (Tool::Hole, &mut level.holes),
+            (Tool::Food, &mut level.foods),
+            (Tool::Spawner, &mut level.spawners),
+        ];
+        for (kind, cells) in layers {
+            if kind != tool {
+                cells.retain(|&c| c != pos);
+            }
+        }
+    }
+}
+
+/// Replace the cubes of the edited cells, or all of them after a load, and
+/// rebuild the snake head and the markers
+fn refresh_preview(
+    mut commands:   Commands,
+    game_assets:    Res<GlobalAssets>,
+    mut editor:     ResMut<Editor>,
+    mut map_state:  ResMut<MapState>,
+    mut snake:      Query<&mut Transform, With<Snake>>,
+    markers:        Query<Entity, With<EditorMarker>>,
+    mut status:     Query<&mut Text, With<StatusText>>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+    // clicks land on a flat floor, cubes and layers are shown unfolded
+    let level = &editor.level.unfolded();
+    match editor.bypass_change_detection().edited.replace(Vec::new()) {
+        Some(edited) => {
+            for pos in edited {
+                let cube = spawn_cube(&mut commands, &game_assets, level, pos);
+                if let Some(old) = map_state.set_cube(pos, cube) {
+                    commands.entity(old).despawn_recursive();
+                }
+            }
+            map_state.floor = Some(level.clone());
+        }
+        None => {
+            for &cube in map_state.grid.iter().flatten() {
+                commands.entity(cube).despawn_recursive();
+            }
+            let grid = spawn_grid(&mut commands, &game_assets, level);
+            map_state.set_grid(grid, level);
+        }
+    }
+
+    if let Ok(mut transform) = snake.get_single_mut() {
+        transform.translation = level.start.to_world();
+    }
+    for marker in markers.iter() {
+        commands.entity(marker).despawn_recursive();
+    }
+    commands.spawn((
+        EditorMarker,
+        Mesh3d(game_assets.snake_body.clone()),
+        MeshMaterial3d(game_assets.snake_body_mat.clone()),
+        Transform::from_translation((level.start + level.direction.offset()).to_world()).with_scale(Vec3::splat(0.5)),
+    ));
+    for food in level.foods.iter() {
+        commands.spawn((
+            EditorMarker,
+            Mesh3d(game_assets.food.clone()),
+            MeshMaterial3d(game_assets.food_mat.clone()),
+            Transform::from_translation(food.to_world()),
+        ));
+    }
+    // spawners show as a flat food on the floor
+    for spawner in level.spawners.iter() {
+        commands.spawn((
+            EditorMarker,
+            Mesh3d(game_assets.food.clone()),
+            MeshMaterial3d(game_assets.food_mat.clone()),
+            Transform::from_translation(spawner.to_world() + Vec3::Y * -0.4).with_scale(Vec3::new(1.0, 0.2, 1.0)),
+        ));
+    }
+
+    if let Ok(mut text) = status.get_single_mut() {
+        text.0 = format!("{}x{}  {}", level.width, level.height, editor.status);
+    }
+}
+
+fn cleanup_editor(
+    mut commands:   Commands,
+    entities:       Query<Entity, Or<(With<EditorUi>, With<EditorView>, With<EditorMarker>)>>,
+) {
+    for entity in entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<Editor>();
+}
+
+/// The menu builds its own world, drop the one shown while editing
+fn cleanup_preview_world(
+    mut commands:   Commands,
+    entities:       Query<Entity, Or<(With<CubeState>, With<Snake>)>>,
+) {
+    for entity in entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
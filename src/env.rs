@@ -0,0 +1,270 @@
+//! Gym-style environment for training agents on the game rules. It drives a
+//! single-player `SnakeSim` one grid step per action, the same way the game
+//! does, without a window or renderer.
+
+use crate::{
+    food::{Effect, FoodKind},
+    grid::GridPos,
+    level::{Level, LevelError},
+    replay::start_game,
+    rng::GameRng,
+    sim::{CellKind, DeathCause, Direction, SimEvent, SnakeSim, OBSTACLE_MOVE_TIME, OBSTACLE_WARN_TIME},
+};
+
+/// Reward for the step that ends the game, eating food earns its score
+pub const DEATH_REWARD: f32 = -1.0;
+
+/// Channels of an `Observation`, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Cells the head dies on right now: walls, sinking walls, holes and the
+    /// blocks of patrols and sweepers
+    Walls,
+    Body,
+    Head,
+    Food,
+    /// Floor about to rise into a wall, from 0 when the warning starts to 1
+    /// when it closes
+    Warnings,
+}
+impl Channel {
+    pub const ALL: [Channel; 5] = [Channel::Walls, Channel::Body, Channel::Head, Channel::Food, Channel::Warnings];
+}
+
+/// The arena as a `Channel::ALL.len() x height x width` tensor, rows along `z`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+    /// Share of each of `Effect::ALL` left, 0 when it is not running
+    pub effects: [f32; Effect::ALL.len()],
+}
+
+impl Observation {
+    fn new(width: usize, height: usize) -> Self {
+        Self { width, height, data: vec![0.0; Channel::ALL.len() * width * height], effects: [0.0; Effect::ALL.len()] }
+    }
+
+    fn index(&self, channel: Channel, pos: GridPos) -> usize {
+        (channel as usize * self.height + pos.z as usize) * self.width + pos.x as usize
+    }
+
+    pub fn get(&self, channel: Channel, pos: GridPos) -> f32 {
+        self.data[self.index(channel, pos)]
+    }
+
+    fn set(&mut self, channel: Channel, pos: GridPos, value: f32) {
+        let i = self.index(channel, pos);
+        self.data[i] = value;
+    }
+}
+
+/// What happened during a step besides the reward
+#[derive(Debug, Clone, Default)]
+pub struct StepInfo {
+    pub score: i32,
+    pub ticks: u64,
+    pub length: usize,
+    pub ate: Option<FoodKind>,
+    pub death: Option<DeathCause>,
+    /// Everything the simulation reported, obstacle changes included
+    pub events: Vec<SimEvent>,
+}
+
+pub struct SnakeEnv {
+    level: Level,
+    sim: SnakeSim,
+    rng: GameRng,
+}
+
+impl SnakeEnv {
+    /// Games on `level`, call `reset` to start one. Invalid levels are
+    /// refused, and so are those with cells the channels can't show: portals,
+    /// belts, ramps, stacked layers and cubes.
+    pub fn new(level: Level) -> Result<Self, LevelError> {
+        level.validate()?;
+        let unsupported = [
+            (!level.portals.is_empty(), "portals"),
+            (!level.conveyors.is_empty(), "conveyors"),
+            (!level.ramps.is_empty() || level.layers > 1, "layers"),
+            (level.cube_net().is_some(), "a cube surface"),
+        ];
+        if let Some((_, what)) = unsupported.iter().find(|(found, _)| *found) {
+            return Err(LevelError::Invalid(format!("observations can't show {what}")));
+        }
+        let (sim, rng) = start_game(&level, 0, 1, 0);
+        Ok(Self { level, sim, rng })
+    }
+
+    /// Start a new game the way the game itself starts one with this seed
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        (self.sim, self.rng) = start_game(&self.level, seed, 1, 0);
+        self.observe()
+    }
+
+    /// Turn towards `action` and take one grid step. Turning back into the
+    /// neck is ignored like a reversed key press. Stepping a finished game
+    /// changes nothing.
+    pub fn step(&mut self, action: Direction) -> (Observation, f32, bool, StepInfo) {
+        let score = self.sim.score();
+        self.sim.turn(action);
+        let events = self.sim.tick(&mut self.rng);
+
+        let mut info = StepInfo {
+            score: self.sim.score(),
+            ticks: self.sim.ticks(),
+            length: self.sim.segments().len(),
+            ..Default::default()
+        };
+        for event in events.iter() {
+            match *event {
+                SimEvent::AteFood { snake: 0, kind, .. } => info.ate = Some(kind),
+                SimEvent::Died { snake: 0, cause } => info.death = Some(cause),
+                _ => {}
+            }
+        }
+        info.events = events;
+
+        let done = !self.sim.is_alive();
+        let reward = if info.death.is_some() { DEATH_REWARD } else { (self.sim.score() - score) as f32 };
+        (self.observe(), reward, done, info)
+    }
+
+    pub fn sim(&self) -> &SnakeSim {
+        &self.sim
+    }
+
+    fn observe(&self) -> Observation {
+        let sim = &self.sim;
+        let mut obs = Observation::new(sim.width(), sim.height());
+        for (pos, kind) in sim.cells() {
+            match kind {
+                CellKind::Rising(left) => {
+                    let warned = 1.0 - left / (OBSTACLE_WARN_TIME + OBSTACLE_MOVE_TIME);
+                    obs.set(Channel::Warnings, pos, warned.clamp(0.0, 1.0));
+                }
+                kind if kind.is_blocking() => obs.set(Channel::Walls, pos, 1.0),
+                _ => {}
+            }
+        }
+        for pos in sim.hazards() {
+            obs.set(Channel::Walls, pos, 1.0);
+        }
+        for &pos in sim.segments().iter().skip(1) {
+            obs.set(Channel::Body, pos, 1.0);
+        }
+        obs.set(Channel::Head, sim.head(), 1.0);
+        for food in sim.foods() {
+            obs.set(Channel::Food, food.pos, 1.0);
+        }
+        for &(effect, left) in sim.effects() {
+            if let Some(i) = Effect::ALL.iter().position(|&e| e == effect) {
+                obs.effects[i] = left / effect.duration();
+            }
+        }
+        obs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::ObstacleSchedule;
+
+    /// A 12x8 open arena without random obstacles, wider than high so rows
+    /// and columns can't be mixed up
+    fn quiet_level() -> Level {
+        let mut level = Level::open(12, 8);
+        level.obstacles = ObstacleSchedule::Off;
+        level
+    }
+
+    fn ahead(env: &SnakeEnv) -> GridPos {
+        env.sim.head() + env.sim.direction().offset()
+    }
+
+    #[test]
+    fn reset_starts_the_same_game_for_a_seed() {
+        let mut env = SnakeEnv::new(quiet_level()).unwrap();
+        let first = env.reset(11);
+        let steps: Vec<_> = (0..3).map(|_| env.step(Direction::Left).0).collect();
+        assert_eq!(env.reset(11), first);
+        for obs in steps {
+            assert_eq!(env.step(Direction::Left).0, obs);
+        }
+    }
+
+    #[test]
+    fn eating_earns_the_food_score() {
+        let mut level = quiet_level();
+        level.foods.push(level.start + level.direction.offset());
+        let mut env = SnakeEnv::new(level).unwrap();
+        env.reset(0);
+        let (obs, reward, done, info) = env.step(Direction::Up);
+        assert_eq!(reward, FoodKind::Normal.points() as f32);
+        assert!(!done);
+        assert_eq!(info.ate, Some(FoodKind::Normal));
+        assert_eq!((info.score, info.length), (FoodKind::Normal.points(), 2));
+        assert_eq!(obs.get(Channel::Head, env.sim.head()), 1.0);
+    }
+
+    #[test]
+    fn dying_ends_the_game() {
+        let mut level = quiet_level();
+        level.walls.push(level.start + level.direction.offset());
+        let mut env = SnakeEnv::new(level).unwrap();
+        let obs = env.reset(0);
+        assert_eq!(obs.get(Channel::Walls, ahead(&env)), 1.0);
+        let (_, reward, done, info) = env.step(Direction::Up);
+        assert_eq!(reward, DEATH_REWARD);
+        assert!(done);
+        assert_eq!(info.death, Some(DeathCause::Obstacle));
+        // a finished game stays finished
+        let (_, reward, done, info) = env.step(Direction::Left);
+        assert_eq!((reward, done, info.ticks), (0.0, true, 1));
+    }
+
+    #[test]
+    fn channels_are_planes_of_rows() {
+        let mut env = SnakeEnv::new(quiet_level()).unwrap();
+        let obs = env.reset(0);
+        assert_eq!(obs.data.len(), Channel::ALL.len() * 8 * 12);
+        let pos = GridPos::new(7, 3);
+        assert_eq!(obs.index(Channel::Head, pos), (2 * 8 + 3) * 12 + 7);
+        let head = env.sim.head();
+        assert_eq!(obs.data[obs.index(Channel::Head, head)], 1.0);
+        assert_eq!(obs.data.iter().filter(|&&value| value != 0.0).count(), 1 + env.sim.foods().len());
+    }
+
+    #[test]
+    fn warnings_grow_until_the_wall_rises() {
+        let mut env = SnakeEnv::new(quiet_level()).unwrap();
+        env.reset(0);
+        let pos = GridPos::new(1, 1);
+        let total = OBSTACLE_WARN_TIME + OBSTACLE_MOVE_TIME;
+        for (left, warned) in [(total, 0.0), (total / 2.0, 0.5), (0.0, 1.0)] {
+            env.sim.set_cell(pos, CellKind::Rising(left));
+            assert_eq!(env.observe().get(Channel::Warnings, pos), warned);
+        }
+    }
+
+    #[test]
+    fn levels_the_channels_cant_show_are_refused() {
+        let mut portals = quiet_level();
+        portals.portals.push((GridPos::new(1, 1), GridPos::new(10, 6)));
+        assert!(SnakeEnv::new(portals).is_err());
+        assert!(SnakeEnv::new(Level::cube(4)).is_err());
+        assert!(SnakeEnv::new(quiet_level()).is_ok());
+    }
+
+    #[test]
+    fn invalid_levels_are_refused() {
+        let mut outside = quiet_level();
+        outside.start = GridPos::new(12, 3);
+        assert!(matches!(SnakeEnv::new(outside), Err(LevelError::Invalid(_))));
+        let mut negative = quiet_level();
+        negative.walls.push(GridPos::new(-1, 0));
+        assert!(SnakeEnv::new(negative).is_err());
+    }
+}
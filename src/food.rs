@@ -0,0 +1,160 @@
+//! Kinds of food and the timed effects some of them leave on the snake.
+
+use std::fmt;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::grid::GridPos;
+
+/// Tail segments a shrink food takes off
+pub const SHRINK_LENGTH: usize = 3;
+/// Cells around the head, in each direction, that a bomb clears of obstacles
+pub const BOMB_RADIUS: i32 = 3;
+/// Speed multiplier while slowed down
+pub const SLOW_FACTOR: f32 = 0.6;
+/// Speed multiplier while sped up
+pub const FAST_FACTOR: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FoodKind {
+    Normal,
+    /// Worth more points
+    Golden,
+    /// Takes the tail in instead of growing it
+    Shrink,
+    Slow,
+    Fast,
+    /// Moves through its own body and the obstacles for a while
+    Ghost,
+    /// Sinks the obstacles around the head
+    Bomb,
+}
+
+impl FoodKind {
+    pub const ALL: [FoodKind; 7] = [
+        FoodKind::Normal,
+        FoodKind::Golden,
+        FoodKind::Shrink,
+        FoodKind::Slow,
+        FoodKind::Fast,
+        FoodKind::Ghost,
+        FoodKind::Bomb,
+    ];
+
+    pub fn points(self) -> i32 {
+        match self {
+            FoodKind::Golden => 5,
+            _ => 1,
+        }
+    }
+
+    /// Relative chance of being picked for a new food
+    pub fn weight(self) -> u32 {
+        match self {
+            FoodKind::Normal => 60,
+            FoodKind::Golden | FoodKind::Shrink | FoodKind::Slow | FoodKind::Fast => 8,
+            FoodKind::Ghost | FoodKind::Bomb => 4,
+        }
+    }
+
+    /// Seconds the food stays before another one replaces it, normal food stays
+    pub fn lifetime(self) -> Option<f32> {
+        match self {
+            FoodKind::Normal => None,
+            FoodKind::Golden => Some(6.0),
+            _ => Some(10.0),
+        }
+    }
+
+    /// The effect eating it starts
+    pub fn effect(self) -> Option<Effect> {
+        match self {
+            FoodKind::Slow => Some(Effect::Slow),
+            FoodKind::Fast => Some(Effect::Fast),
+            FoodKind::Ghost => Some(Effect::Ghost),
+            _ => None,
+        }
+    }
+
+    /// A kind drawn by spawn weight
+    pub fn choose<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        let total: u32 = Self::ALL.iter().map(|kind| kind.weight()).sum();
+        let mut roll = rng.gen_range(0..total);
+        for kind in Self::ALL {
+            if roll < kind.weight() {
+                return kind;
+            }
+            roll -= kind.weight();
+        }
+        FoodKind::Normal
+    }
+}
+
+impl fmt::Display for FoodKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FoodKind::Normal => "normal",
+            FoodKind::Golden => "golden",
+            FoodKind::Shrink => "shrink",
+            FoodKind::Slow => "slow",
+            FoodKind::Fast => "fast",
+            FoodKind::Ghost => "ghost",
+            FoodKind::Bomb => "bomb",
+        })
+    }
+}
+
+/// A timed change to the snake, started by eating some kinds of food
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Effect {
+    Slow,
+    Fast,
+    Ghost,
+}
+
+impl Effect {
+    pub const ALL: [Effect; 3] = [Effect::Slow, Effect::Fast, Effect::Ghost];
+
+    /// Seconds it lasts
+    pub fn duration(self) -> f32 {
+        match self {
+            Effect::Slow | Effect::Fast => 6.0,
+            Effect::Ghost => 5.0,
+        }
+    }
+
+    /// The effect this one replaces
+    pub fn cancels(self) -> Option<Effect> {
+        match self {
+            Effect::Slow => Some(Effect::Fast),
+            Effect::Fast => Some(Effect::Slow),
+            Effect::Ghost => None,
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Effect::Slow => "slow",
+            Effect::Fast => "fast",
+            Effect::Ghost => "ghost",
+        })
+    }
+}
+
+/// A food lying in the arena
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FoodItem {
+    pub pos: GridPos,
+    pub kind: FoodKind,
+    /// Seconds until it goes away, `None` for food that stays
+    pub expires_in: Option<f32>,
+}
+
+impl FoodItem {
+    pub fn new(pos: GridPos, kind: FoodKind) -> Self {
+        Self { pos, kind, expires_in: kind.lifetime() }
+    }
+}
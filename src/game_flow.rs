@@ -1,16 +1,15 @@
-use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use snake_3d::{food::FoodKind, grid::GridPos, replay::start_game, rng::GameRng, scores::{game_mode, HighScores, ScoreEntry}, sim::SnakeSim};
 use crate::player::*;
 use crate::animation::*;
+use crate::replay_viewer::Playback;
+use crate::rivals::RivalConfig;
 use crate::utils::*;
 use crate::STATE_TRANSITION_TIME;
-use crate::{CubeState, MapState, GameState, GlobalAssets, MAP_SIZE};
+use crate::camera::{CameraFollowTarget, TopdownCamera};
+use crate::{levels::ActiveLevel, spawn_grid, spawn_snake_head, CubeState, MapState, GameState, GlobalAssets, Playing, SeedConfig, Simulating};
 
 
-const BOOST_SPEED_AT: [usize; 5] = [
-    5, 10, 20, 30, 40 // 
-];
-
 pub struct GameFlowPlugin;
 impl Plugin for GameFlowPlugin {
     fn build(&self, app: &mut App) {
@@ -19,151 +18,298 @@ impl Plugin for GameFlowPlugin {
             .add_event::<SpawnSnakeTail>()
             .add_event::<MapModifyEvent>()
             .add_event::<GameOver>()
-            .add_systems(OnEnter(GameState::InGame), 
-            |mut spawn_food_event: EventWriter<SpawnFoodEvent>| {
-                spawn_food_event.send(SpawnFoodEvent);
-            })
-            .add_systems(OnEnter(GameState::InGame), (spawn_hud))
-            .add_systems(OnEnter(GameState::GameOver), on_game_over)
-            .add_systems(OnExit(GameState::GameOver), cleanup_game)
-            .add_systems(Update, (
-                spawn_food,
+            .init_resource::<GameMode>()
+            .insert_resource(load_high_scores())
+            .add_systems(OnEnter(Playing), (
+                start_run,
+                fit_world_to_sim.after(start_run),
+                spawn_other_snakes.after(start_run),
+                spawn_hazards.after(start_run),
+                spawn_hud.after(spawn_other_snakes),
             ))
+            .add_systems(OnEnter(GameState::GameOver), (
+                on_game_over,
+                record_high_score.run_if(not(resource_exists::<Playback>)),
+            ))
+            .add_systems(OnExit(GameState::GameOver), cleanup_game)
+            // games quit from the pause menu
+            .add_systems(OnTransition { exited: GameState::InGame, entered: GameState::Menu }, cleanup_game)
+            .add_systems(OnTransition { exited: GameState::Replay, entered: GameState::Menu }, cleanup_game)
+            .add_systems(OnEnter(GameState::Restarting), cleanup_game)
+            .add_systems(Update, resume_after_restart.run_if(in_state(GameState::Restarting)))
+            .add_systems(Update, spawn_food)
             .add_systems(Update, (
-                spawn_snake_tail,
                 update_score,
+                update_effects,
                 check_for_game_end,
                 update_play_time,
-                MapState::update,
+            ).run_if(in_state(Playing)))
+            .add_systems(Update, (
+                spawn_snake_tail,
+                blink_expiring_food,
                 map_modify_event_listener,
-            ).run_if(in_state(GameState::InGame)))
+                sync_cube_states,
+                move_hazards,
+            ).run_if(in_state(Simulating)))
             .add_systems(Update, MapState::update_transition_timer.run_if(in_state(GameState::GameOver)));
     }
 }
 #[derive(Event)]
-pub struct MapModifyEvent {
-    pub cube_count: usize,
+pub enum MapModifyEvent {
+    Raise(Vec<GridPos>),
+    Lower(Vec<GridPos>),
+    /// Cubes that were about to rise go back to floor at once
+    Clear(Vec<GridPos>),
 }
+/// One of the snakes grew, with its index
 #[derive(Event)]
-pub struct SpawnSnakeTail;
+pub struct SpawnSnakeTail(pub usize);
+/// Players at the keyboard, picked on the menu. Versus splits the screen
+/// between two snakes in the same arena.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GameMode {
+    #[default]
+    Solo,
+    Versus,
+}
+impl GameMode {
+    pub fn players(self) -> usize {
+        match self {
+            GameMode::Solo => 1,
+            GameMode::Versus => 2,
+        }
+    }
+}
+/// Food was placed in the simulation, spawn its entity
 #[derive(Event)]
-pub struct SpawnFoodEvent;
+pub struct SpawnFoodEvent(pub GridPos, pub FoodKind);
+#[derive(Component)]
+pub struct Food {
+    pub cell: GridPos,
+}
+/// Seconds before it goes away that a food starts blinking
+const FOOD_BLINK_TIME: f32 = 2.0;
+/// A block of a patrol or sweeper, following `SnakeSim::hazards()[index]`
 #[derive(Component)]
-pub struct Food;
+pub struct Hazard {
+    index: usize,
+}
+/// How fast moving obstacles slide onto their current cell
+const HAZARD_FOLLOW_SPEED: f32 = 12.0;
+/// Score of the snake with this index
 #[derive(Component)]
-pub struct ScoreText;
+pub struct ScoreText(pub usize);
 #[derive(Component)]
 pub struct PlayTimeText;
+#[derive(Component)]
+pub struct EffectsText(pub usize);
 #[derive(Event)]
 pub struct GameOver;
 
 
-#[derive(SystemParam)]
-struct PositionQueryParam<'w, 's> {
-    cube_query:     Query<'w, 's, (Entity, &'static CubeState), Without<DeactiveCubeAnimation>>,
-    player_query:   Query<'w, 's, &'static Snake>,
-    player_body_query: Query<'w, 's, &'static SnakeBody>,
-    food_query:     Query<'w, 's, &'static Transform, With<Food>>,
-}
-
-impl<'w, 's> PositionQueryParam<'w, 's> {
-    fn get_empty_cubes(&self) -> Vec<(Entity, (usize, usize))> {
-        let mut walkable_poses = self.cube_query.iter()
-            .filter(|(_, c)| c.walkable)
-            .map(|(entity, c)| (entity, c.pos))
-            .collect::<Vec<_>>();
-        // println!("{:?}", walkable_poses);
-        let mut player_poses = Vec::new();
-
-        if let Ok(player) = self.player_query.get_single() {
-            player_poses.push((player.target_position.x as usize, player.target_position.z as usize));
-            for body in self.player_body_query.iter() {
-                player_poses.push((body.target_position.x as usize, body.target_position.z as usize));
+fn map_modify_event_listener(
+    mut ev_reader:  EventReader<MapModifyEvent>,
+    mut commands:   Commands,
+    sim:            Res<SnakeSim>,
+    map_state:      Res<MapState>,
+    game_assets:    Res<GlobalAssets>,
+    mut cubes_query: Query<(&mut Transform, Option<&DeactiveCubeAnimation>), With<CubeState>>,
+) {
+    for ev in ev_reader.read() {
+        match ev {
+            MapModifyEvent::Raise(cells) => {
+                for cell in cells.iter() {
+                    let Some(e) = map_state.cube(*cell) else { continue };
+                    if let Ok((transform, _)) = cubes_query.get(e) {
+                        commands.entity(e).insert(DeactiveCubeAnimation::new(
+                            transform.translation, 
+                            transform.translation + sim.level().cell_normal(*cell)
+                        ));
+                    }
+                }
             }
-            // add some position around snake head
-            let offset: usize = (player.speed.min(10.0)) as usize;
-            for i in 0..offset {
-                for j in 0..offset {
-                    let pos = (i, j);
-                    if !player_poses.contains(&pos) {
-                        player_poses.push(pos);
+            MapModifyEvent::Lower(cells) => {
+                for cell in cells.iter() {
+                    let Some(e) = map_state.cube(*cell) else { continue };
+                    if let Ok((transform, _)) = cubes_query.get(e) {
+                        commands.entity(e).remove::<DeactiveCubeAnimation>();
+                        commands.entity(e).insert(ActiveCubeAnimation::new(
+                            transform.translation,
+                            transform.translation - sim.level().cell_normal(*cell)
+                        ));
                     }
                 }
             }
-        }
-        
-        
-        if let Ok(food_transform) = self.food_query.get_single() {
-            let mut food_poses = Vec::new();
-            food_poses.push((food_transform.translation.x as usize, food_transform.translation.z as usize)) ;
-            let food_offset: usize = 3;
-            for i in 0..food_offset {
-                for j in 0..food_offset {
-                    let pos = (i, j);
-                    if !food_poses.contains(&pos) {
-                        food_poses.push(pos);
+            MapModifyEvent::Clear(cells) => {
+                for cell in cells.iter() {
+                    let Some(e) = map_state.cube(*cell) else { continue };
+                    if let Ok((mut transform, Some(anim))) = cubes_query.get_mut(e) {
+                        transform.translation = anim.from;
+                        commands.entity(e)
+                            .remove::<DeactiveCubeAnimation>()
+                            .insert(MeshMaterial3d(game_assets.map_cube_mat.clone()));
                     }
                 }
             }
-            walkable_poses.retain(|&(_, pos)| !food_poses.contains(&pos));
         }
+    }
+}
+
+/// Mirror the simulation grid onto the cubes
+fn sync_cube_states(
+    sim:        Res<SnakeSim>,
+    mut cubes:  Query<&mut CubeState>,
+) {
+    for mut cube in cubes.iter_mut() {
+        let walkable = !sim.cell(cube.pos).is_blocking();
+        if cube.walkable != walkable {
+            cube.walkable = walkable;
+        }
+    }
+}
 
-        walkable_poses
-        .into_iter()
-        .filter(|(_, x)| !player_poses.contains(x))
-        // .map(|(entity, _)| entity)
-        .collect::<Vec<_>>()
+/// Start a fresh simulation, from the replay being watched or from a new seed
+pub fn start_run(
+    seed_config:    Res<SeedConfig>,
+    level:          Res<ActiveLevel>,
+    mode:           Res<GameMode>,
+    rivals:         Res<RivalConfig>,
+    playback:       Option<Res<Playback>>,
+    mut sim:        ResMut<SnakeSim>,
+    mut rng:        ResMut<GameRng>,
+    mut spawn_food_event: EventWriter<SpawnFoodEvent>,
+) {
+    let (seed, level, players, rivals) = match playback.as_deref() {
+        Some(Playback(replay)) => (replay.seed, &replay.level, replay.snakes.saturating_sub(replay.rivals), replay.rivals),
+        None => (seed_config.0.unwrap_or_else(rand::random), &level.0, mode.players(), rivals.count),
+    };
+    (*sim, *rng) = start_game(level, seed, players, rivals);
+    for food in sim.foods() {
+        spawn_food_event.send(SpawnFoodEvent(food.pos, food.kind));
     }
+    info!("game seed: {}", seed);
 }
 
-fn map_modify_event_listener(
-    mut ev_reader:  EventReader<MapModifyEvent>,
+/// Rebuild the floor when the game runs on another level than the one it was
+/// built from, as replays recorded on a different level and cube or layered
+/// levels tried out from the editor's flat view do
+fn fit_world_to_sim(
     mut commands:   Commands,
-    mut cubes_query: Query<&mut Transform, (Without<DeactiveCubeAnimation>, Without<Food>)>,
-    inactive_cubes: Query<(Entity, &Transform),(With<DeactiveCubeAnimation>, Without<ActiveCubeAnimation>, Without<Food>)>,
-    pos_param:      PositionQueryParam,
+    game_assets:    Res<GlobalAssets>,
+    sim:            Res<SnakeSim>,
+    mut map_state:  ResMut<MapState>,
+    mut snake_query: Query<(&mut Snake, &mut Transform), Without<SnakeBody>>,
+) {
+    if map_state.floor.as_ref() != Some(sim.level()) {
+        for &cube in map_state.grid.iter().flatten() {
+            commands.entity(cube).despawn_recursive();
+        }
+        let grid = spawn_grid(&mut commands, &game_assets, sim.level());
+        map_state.set_grid(grid, sim.level());
+    }
+    for (mut snake, mut transform) in snake_query.iter_mut() {
+        let head = sim.snake(snake.index).head();
+        snake.prev_cell = head;
+        snake.cell = head;
+        transform.translation = sim.level().cell_world(head);
+    }
+}
 
+/// Heads of the snakes after the first, which `spawn_world` has already
+/// placed. The other players get a camera too, each view takes its slice of
+/// the window.
+fn spawn_other_snakes(
+    mut commands:   Commands,
+    game_assets:    Res<GlobalAssets>,
+    sim:            Res<SnakeSim>,
+    level:          Res<ActiveLevel>,
 ) {
-    for ev in ev_reader.read() {
-        let cubes = pos_param
-            .get_empty_cubes()
-            .choose_random_n(ev.cube_count)
-            .iter()
-            .map(|c| c.0)
-            .collect::<Vec<_>>();
-        for e in cubes.iter() {
-            if let Ok(transform) = cubes_query.get_mut(*e) {
-                commands.entity(*e).insert(DeactiveCubeAnimation::new(
-                    // game_assets.map_cube_mat.clone(), 
-                    transform.translation, 
-                    transform.translation.with_y(transform.translation.y + 1.0)
-                ));
-                // cube_state.walkable = false;
-            }
-           
+    for (index, state) in sim.snakes().iter().enumerate().skip(1) {
+        let head = spawn_snake_head(&mut commands, &game_assets, sim.level(), Snake { index, ..Snake::new(state.head()) });
+        if index >= sim.players() {
+            continue;
         }
+        commands.entity(head).insert(CameraFollowTarget(index));
+        commands.spawn((
+            Camera3d::default(),
+            Camera {
+                hdr: true,
+                // the first view stays the default one for the UI
+                order: -(index as isize),
+                ..default()
+            },
+            Transform::from_translation(sim.level().cell_world(state.head()) + level.camera_offset()),
+            TopdownCamera { view: index, ..TopdownCamera::with_offset(level.camera_offset()) },
+        ));
+    }
+}
 
-        inactive_cubes.iter().for_each(|(entity, transform)| {
-            commands.entity(entity).remove::<DeactiveCubeAnimation>();
-            commands.entity(entity).insert(ActiveCubeAnimation::new(
-                // game_assets.map_cube_mat_emission.clone(),
-                transform.translation,
-                transform.translation.with_y(transform.translation.y - 1.0)
-            ));
-        });
+pub fn spawn_hazards(
+    mut commands:   Commands,
+    game_assets:    Res<GlobalAssets>,
+    sim:            Res<SnakeSim>,
+) {
+    for (index, cell) in sim.hazards().into_iter().enumerate() {
+        commands.spawn((
+            Hazard { index },
+            Mesh3d(game_assets.map_cube.clone()),
+            MeshMaterial3d(game_assets.red_mat.clone()),
+            Transform::from_translation(sim.level().cell_world(cell)),
+        ));
+    }
+}
+
+fn move_hazards(
+    time:           Res<Time>,
+    sim:            Res<SnakeSim>,
+    mut hazards:    Query<(&mut Transform, &Hazard)>,
+) {
+    let cells = sim.hazards();
+    let follow = (HAZARD_FOLLOW_SPEED * time.delta_secs()).min(1.0);
+    for (mut transform, hazard) in hazards.iter_mut() {
+        let Some(&cell) = cells.get(hazard.index) else { continue };
+        let target = sim.level().cell_world(cell);
+        transform.translation = transform.translation.lerp(target, follow);
     }
 }
+
 #[derive(Component)]
-struct HUD;
+struct Hud;
 fn spawn_hud(
     mut commands: Commands,
     mut map_state: ResMut<MapState>,
+    sim: Res<SnakeSim>,
+    rng: Res<GameRng>,
+    cameras: Query<(Entity, &TopdownCamera)>,
 ) {
     map_state.time_elapsed = 0.0;
     map_state.score = 0;
+    map_state.seed = rng.seed();
+
+    // the other players' scores show in their own views
+    for (camera, view) in cameras.iter().filter(|(_, camera)| (1..sim.players()).contains(&camera.view)) {
+        commands.spawn((
+            Hud,
+            TargetCamera(camera),
+            Node {
+                flex_direction: FlexDirection::Column,
+                margin: UiRect::all(Val::Px(15.0)),
+                ..default()
+            },
+        )).with_children(|parent| {
+            parent.spawn((
+                ScoreText(view.view),
+                Text::new(""),
+            ));
+            parent.spawn((
+                EffectsText(view.view),
+                Text::new(""),
+            ));
+        });
+    }
 
     commands.spawn((
-        HUD,
+        Hud,
         Node {
             justify_content: JustifyContent::Center,
             align_items: AlignItems::Start,
@@ -180,77 +326,116 @@ fn spawn_hud(
             Text::new("time: 0"),
         ));
         parent.spawn((
-            ScoreText,
-            Text::new("score: 0"),
+            ScoreText(0),
+            Text::new(""),
+        ));
+        parent.spawn((
+            EffectsText(0),
+            Text::new(""),
         ));
+        for rival in sim.players()..sim.snakes().len() {
+            parent.spawn((
+                ScoreText(rival),
+                Text::new(""),
+            ));
+        }
        
         
     });
 }
 fn update_score(
+    sim:            Res<SnakeSim>,
     mut map_state:  ResMut<MapState>,
-    mut query:      Query<&mut Text, With<ScoreText>>,
-    mut evs:        EventReader<SpawnSnakeTail>,
+    mut query:      Query<(&mut Text, &ScoreText)>,
 ) {
-    for _ in evs.read() {
-        let mut score_text = match query.get_single_mut() {
-            Ok(z) => z,
-            Err(_) => return,
+    map_state.score = sim.score();
+    let players = sim.players();
+    for (mut text, score) in query.iter_mut() {
+        let Some(snake) = sim.snakes().get(score.0) else { continue };
+        let mut line = if score.0 >= players {
+            format!("rival {}: {}", score.0 - players + 1, snake.score())
+        } else if players > 1 {
+            format!("player {}: {}", score.0 + 1, snake.score())
+        } else {
+            format!("score: {}", snake.score())
         };
-        map_state.score += 1;
-        score_text.0 = format!("score: {}", map_state.score);
+        if !snake.is_alive() {
+            line.push_str(" - out");
+        }
+        if text.0 != line {
+            text.0 = line;
+        }
+    }
+}
+
+/// Food about to run out blinks
+fn blink_expiring_food(
+    sim:            Res<SnakeSim>,
+    mut foods:      Query<(&Food, &mut Visibility)>,
+) {
+    for (food, mut visibility) in foods.iter_mut() {
+        let left = sim.food_at(food.cell).and_then(|food| food.expires_in).unwrap_or(f32::MAX);
+        let shown = left > FOOD_BLINK_TIME || (left * 6.0) as i32 % 2 == 0;
+        visibility.set_if_neq(if shown { Visibility::Inherited } else { Visibility::Hidden });
+    }
+}
+
+/// List the timed effects running with the seconds they have left
+fn update_effects(
+    sim:            Res<SnakeSim>,
+    mut query:      Query<(&mut Text, &EffectsText)>,
+) {
+    for (mut text, effects) in query.iter_mut() {
+        let Some(snake) = sim.snakes().get(effects.0) else { continue };
+        let effects: Vec<String> = snake.effects().iter()
+            .map(|(effect, left)| format!("{effect} {left:.1}s"))
+            .collect();
+        let line = effects.join("  ");
+        if text.0 != line {
+            text.0 = line;
+        }
     }
-    
 }
 
 fn update_play_time(
-    map_state: Res<MapState>,
-    mut query: Query<&mut Text, With<PlayTimeText>>,
+    sim:            Res<SnakeSim>,
+    mut map_state:  ResMut<MapState>,
+    mut query:      Query<&mut Text, With<PlayTimeText>>,
 ) {
     let mut score_text = match query.get_single_mut() {
         Ok(z) => z,
         Err(_) => return,
     };
+    map_state.time_elapsed = sim.time_elapsed();
     score_text.0 = format!("time: {}", format_time(map_state.time_elapsed));
 }
 
 fn spawn_food(
     mut commands:   Commands,
     game_assets:    Res<GlobalAssets>,
-    map_state:      Res<MapState>,
-    pos_param:      PositionQueryParam,
+    sim:            Res<SnakeSim>,
     mut spawn_food_event: EventReader<SpawnFoodEvent>,
 ) {
-    for _ in spawn_food_event.read() {
-
-        // let empty_poses = get_empty_positions(&cube_query, &player_query, &player_body_query, &food_query);
-        let empty_poses = pos_param.get_empty_cubes()
-            .iter()
-            .map(|x|x.1)
-            .collect::<Vec<_>>();
-        if let Some(spawn_pos) = empty_poses.choose_random() {
-            commands.spawn((
-                Food,
-                FoodAnimation::default(),
-                Mesh3d(game_assets.food.clone()),
-                Transform::from_xyz(spawn_pos.0 as f32, 0.0, spawn_pos.1 as f32),
-                MeshMaterial3d(game_assets.food_mat.clone()),
-            )).with_children(|parent| {
-                parent.spawn((
-                    SpotLight {
-                        intensity: 5_000_000.0,
-                        range: 10.0,
-                        shadows_enabled: true,
-                        ..default()
-                    },
-                    Transform::from_xyz(0.0, 3.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
-                ));
-            });
-        } else {
-            warn!("No available position found for spawning FOOD!")
-        }
-    
-       
+    for &SpawnFoodEvent(spawn_pos, kind) in spawn_food_event.read() {
+        let origin = sim.level().cell_world(spawn_pos);
+        commands.spawn((
+            Food { cell: spawn_pos },
+            FoodAnimation::new(origin),
+            Mesh3d(game_assets.food.clone()),
+            Transform::from_translation(origin)
+                .with_rotation(Quat::from_rotation_arc(Vec3::Y, sim.level().cell_normal(spawn_pos))),
+            MeshMaterial3d(game_assets.food_material(kind)),
+        )).with_children(|parent| {
+            parent.spawn((
+                SpotLight {
+                    intensity: 5_000_000.0,
+                    range: 10.0,
+                    shadows_enabled: true,
+                    ..default()
+                },
+                Transform::from_xyz(0.0, 3.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ));
+        });
     }
 }
 
@@ -259,30 +444,20 @@ pub struct BodyIndex(pub usize);
 fn spawn_snake_tail(
     mut commands:       Commands,
     game_assets:        Res<GlobalAssets>,
-    mut snake_query:    Query<(&Transform, &mut Snake), (With<Snake>, Without<SnakeBody>)>,
-    snake_bodies_query: Query<(&Transform, &SnakeBody), (With<SnakeBody>, Without<Snake>)>,
+    sim:                Res<SnakeSim>,
+    mut snake_query:    Query<&mut Snake, Without<SnakeBody>>,
     mut ev_reader:      EventReader<SpawnSnakeTail>,
 ) {
-    for _ in ev_reader.read() {
-        let (snake_transform, mut snake) =  snake_query.single_mut();
-        // let mut tail: SnakeBody = SnakeBody::new(Vec3::ZERO);
-        let mut tail_init_pos: Vec3 = Vec3::ZERO;
-        let tail =  if snake.bodies.is_empty() {
-            tail_init_pos = snake_transform.translation - snake.direction.norm();
-            SnakeBody::new(snake.pos)
-        } else {
-            if let Ok((transform, data)) = snake_bodies_query.get(*snake.bodies.last().unwrap()) {
-                
-                let last_tail_dir = (data.target_position - transform.translation).normalize();
-                tail_init_pos = transform.translation - last_tail_dir;
-                SnakeBody::new(data.target_position-last_tail_dir)
-            } else {
-                SnakeBody::new(Vec3::ZERO)
-            }
-        };
+    for &SpawnSnakeTail(index) in ev_reader.read() {
+        let Some(mut snake) = snake_query.iter_mut().find(|snake| snake.index == index) else { continue };
+        // the new tail sits on the last segment the snake just grew into
+        let tail_cell = sim.snake(index).segments()
+            .get(snake.bodies.len() + 1)
+            .copied()
+            .unwrap_or(snake.cell);
         let entity = commands.spawn((
-            tail,
-            Transform::from_translation(tail_init_pos),
+            SnakeBody::new(tail_cell),
+            Transform::from_translation(sim.level().cell_world(tail_cell)),
             Visibility::Visible,
         ))
         .with_children(|parent| {
@@ -291,74 +466,30 @@ fn spawn_snake_tail(
                 TailAppearAnimation::default(),
                 Mesh3d(game_assets.snake_body.clone()),
                 Transform::from_translation(Vec3::ZERO).with_scale(Vec3::ZERO),
-                MeshMaterial3d(game_assets.snake_body_mat.clone()),
+                MeshMaterial3d(game_assets.body_material(index)),
             ));
         })
         .id();
         snake.bodies.push(entity);
-
-        // Check for speed boost
-        BOOST_SPEED_AT
-            .iter()
-            .any(|&num_body| num_body == snake.bodies.len())
-            .then(||{
-                commands.spawn((
-                    AudioPlayer::<AudioSource>(game_assets.speed_boost.clone()),
-                    PlaybackSettings::DESPAWN,
-                ));
-                snake.speed += 1.0
-            });
     }
    
 }
 
-/// Check player outside of map, check head/body collide with any obstacle cube
+/// End the game once the simulation reports a snake dead
 fn check_for_game_end(
     mut commands:   Commands,
     game_assets:    Res<GlobalAssets>,
     mut next_state: ResMut<NextState<GameState>>,
-    cube_query:     Query<&CubeState>,
-    player:         Query<&Snake>,
-    snake_bodies_query: Query<&SnakeBody>,
+    sim:            Res<SnakeSim>,
 ) {
-    let player = match player.get_single() {
-        Ok(player) => player,
-        Err(_) => return,
-    };
-    let mut end_game = || {
-        commands.spawn((
-            AudioPlayer::<AudioSource>(game_assets.dead.clone()),
-            PlaybackSettings::DESPAWN,
-        ));
-        next_state.set(GameState::GameOver);
+    if sim.is_alive() {
         return;
-    };
-    // Check for player walking outside map
-    if player.target_position.x < 0.0 || player.target_position.z < 0.0 || player.target_position.x >= MAP_SIZE as f32 || player.target_position.z >= MAP_SIZE as f32 {
-        end_game();
-    }
-    let obstacles = cube_query.iter()
-    .filter(|cube| !cube.walkable).map(|c| c.pos).collect::<Vec<_>>();
-
-    // Check collision between player and obstacle cubes
-    if obstacles.iter()
-    .any(|&cube| cube == (player.target_position.x as usize, player.target_position.z as usize)) {
-        end_game();
-    }
-
-    // Check self collision
-    if snake_bodies_query.iter()
-    .any(|body| player.target_position == body.target_position) {
-        end_game()
-    }
-    
-    // check if any body collide with obstacle
-    let body_poses: Vec<(usize, usize)> = snake_bodies_query.iter()
-        .map(|b| (b.target_position.x as usize, b.target_position.z as usize))
-        .collect();
-    if has_common_elements(&body_poses, &obstacles) {
-        end_game();
     }
+    commands.spawn((
+        AudioPlayer::<AudioSource>(game_assets.dead.clone()),
+        PlaybackSettings::DESPAWN,
+    ));
+    next_state.set(GameState::GameOver);
 }
 
 /// Remove game entities spawned during GameState::InGame
@@ -368,13 +499,28 @@ fn cleanup_game(
     snake_bodies_query: Query<Entity, (With<SnakeBody>, Without<Snake>)>,
     food:           Query<Entity, With<Food>>,
     cubes:          Query<Entity, With<CubeState>>,
-    hud:            Query<Entity, With<HUD>>,
+    hazards:        Query<Entity, With<Hazard>>,
+    hud:            Query<Entity, With<Hud>>,
+    cameras:        Query<(Entity, &TopdownCamera)>,
 ) {
-    commands.entity(player.single()).despawn_recursive();
-    commands.entity(food.single()).despawn_recursive();
+    player.iter().for_each(|p| commands.entity(p).despawn_recursive());
+    // the first view is kept for the menu
+    cameras.iter()
+        .filter(|(_, camera)| camera.view > 0)
+        .for_each(|(c, _)| commands.entity(c).despawn_recursive());
+    food.iter().for_each(|f| commands.entity(f).despawn_recursive());
     snake_bodies_query.iter().for_each(|b| commands.entity(b).despawn_recursive());
     cubes.iter().for_each(|c| commands.entity(c).despawn_recursive());
-    commands.entity(hud.single()).despawn_recursive();
+    hazards.iter().for_each(|h| commands.entity(h).despawn_recursive());
+    hud.iter().for_each(|h| commands.entity(h).despawn_recursive());
+}
+
+/// The world was rebuilt, start the same kind of game again
+fn resume_after_restart(
+    playback:       Option<Res<Playback>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    next_state.set(if playback.is_some() { GameState::Replay } else { GameState::InGame });
 }
 
 fn on_game_over(
@@ -382,10 +528,45 @@ fn on_game_over(
     snake_bodies_query: Query<(Entity, &BodyIndex)>,
 ) {
     let body_count = snake_bodies_query.iter().count();
-    let range = create_range(STATE_TRANSITION_TIME as f32 - 2.0, body_count);
+    let range = create_range(STATE_TRANSITION_TIME - 2.0, body_count);
     for (e, body_index) in snake_bodies_query.iter() {
         commands.entity(e).insert(DeadEffect::new(Timer::from_seconds(range[body_index.0], TimerMode::Once)));
     }
 }
 
+fn load_high_scores() -> HighScores {
+    let Some(path) = HighScores::default_path() else {
+        return HighScores::default();
+    };
+    HighScores::load(&path).unwrap_or_else(|err| {
+        warn!("could not load {}: {err}", path.display());
+        HighScores::default()
+    })
+}
 
+/// Add the finished live game to the table and write it back to disk
+fn record_high_score(
+    sim:                Res<SnakeSim>,
+    rng:                Res<GameRng>,
+    rivals:             Res<RivalConfig>,
+    mut high_scores:    ResMut<HighScores>,
+) {
+    // the table ranks single player games
+    if sim.players() > 1 {
+        return;
+    }
+    let entry = ScoreEntry {
+        score: sim.score(),
+        time: sim.time_elapsed(),
+        date: unix_now(),
+        mode: game_mode(sim.level(), sim.snakes().len() - sim.players(), rivals.difficulty),
+        seed: rng.seed(),
+    };
+    let Some(rank) = high_scores.add(entry) else { return };
+    info!("new high score, rank {}", rank + 1);
+    if let Some(path) = HighScores::default_path() {
+        if let Err(err) = high_scores.save(&path) {
+            warn!("could not save {}: {err}", path.display());
+        }
+    }
+}
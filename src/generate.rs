@@ -0,0 +1,286 @@
+//! Procedural arenas: walled rooms, mazes and scattered pillars. Every layout
+//! goes through `keep_connected`, so no floor is ever cut off from the snake.
+
+use std::{fmt, str::FromStr};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grid::GridPos,
+    level::{Level, ObstacleSchedule},
+    sim::MAP_CHANGE_INTERVAL,
+};
+
+/// Cells between two room walls
+const ROOM_SIZE: i32 = 8;
+/// Width of the gaps between rooms
+const DOOR_WIDTH: i32 = 2;
+/// Chance for each remaining maze wall to be knocked out, adding loops
+const MAZE_LOOP_CHANCE: f64 = 0.25;
+/// Arena cells per pillar
+const PILLAR_SPACING: usize = 12;
+/// Arena cells per portal pair, small arenas still get one
+const PORTAL_SPACING: usize = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layout {
+    /// Walled rooms joined by doorways
+    Rooms,
+    /// Corridors two cells wide, with a few loops
+    Maze,
+    /// Single wall cells with free space all around
+    Pillars,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Rooms, Layout::Maze, Layout::Pillars];
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Layout::Rooms => "rooms",
+            Layout::Maze => "maze",
+            Layout::Pillars => "pillars",
+        })
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "rooms" => Ok(Layout::Rooms),
+            "maze" => Ok(Layout::Maze),
+            "pillars" => Ok(Layout::Pillars),
+            other => Err(format!("unknown layout {other:?}, expected rooms, maze or pillars")),
+        }
+    }
+}
+
+/// Wall cells of a layout over the whole arena, before any connectivity check
+pub fn layout_walls<R: Rng + ?Sized>(layout: Layout, width: usize, height: usize, rng: &mut R) -> Vec<GridPos> {
+    let mut wall = vec![false; width * height];
+    match layout {
+        Layout::Rooms => rooms(&mut wall, width, height, rng),
+        Layout::Maze => maze(&mut wall, width, height, rng),
+        Layout::Pillars => pillars(&mut wall, width, height, rng),
+    }
+    let height = height as i32;
+    wall.iter()
+        .enumerate()
+        .filter(|(_, &w)| w)
+        .map(|(i, _)| GridPos::new(i as i32 / height, i as i32 % height))
+        .collect()
+}
+
+/// An arena of the given layout with a few portals, the snake starts in the
+/// middle heading up and pillars keep rising during the game
+pub fn generate_level<R: Rng + ?Sized>(layout: Layout, width: usize, height: usize, rng: &mut R) -> Level {
+    let mut level = Level::open(width, height);
+    level.name = format!("Generated {layout}");
+    level.obstacles = ObstacleSchedule::Generated { interval: MAP_CHANGE_INTERVAL, layout: Layout::Pillars };
+
+    // leave the start and a few cells ahead of it open
+    let start = level.start;
+    let near_start = |pos: &GridPos| (pos.x - start.x).abs() <= 1 && pos.z >= start.z - 3 && pos.z <= start.z + 1;
+    let walls = layout_walls(layout, width, height, rng)
+        .into_iter()
+        .filter(|pos| !near_start(pos))
+        .collect();
+    level.walls = keep_connected(&level, start, walls, |_| false);
+
+    // portal ends on open floor, each pair at least a third of the arena apart
+    let mut floor: Vec<GridPos> = (0..width as i32)
+        .flat_map(|x| (0..height as i32).map(move |z| GridPos::new(x, z)))
+        .filter(|pos| !near_start(pos) && !level.walls.contains(pos))
+        .collect();
+    floor.shuffle(rng);
+    let apart = ((width + height) / 3) as i32;
+    for _ in 0..(width * height / PORTAL_SPACING).max(1) {
+        let Some(a) = floor.pop() else { break };
+        let Some(far) = floor.iter().position(|b| (b.x - a.x).abs() + (b.z - a.z).abs() >= apart) else { break };
+        let b = floor.remove(far);
+        level.portals.push((a, b));
+    }
+    debug_assert!(level.validate().is_ok(), "generated an invalid level: {:?}", level.validate());
+    level
+}
+
+/// Take cells out of `walls` until every cell that `from` reaches without
+/// them is reached with them too. `blocked` cells are closed either way.
+pub fn keep_connected(
+    level: &Level,
+    from: GridPos,
+    mut walls: Vec<GridPos>,
+    blocked: impl Fn(GridPos) -> bool,
+) -> Vec<GridPos> {
+    walls.retain(|pos| level.contains(*pos) && *pos != from);
+    let index = |pos: GridPos| pos.x as usize * level.height + pos.z as usize;
+    let open = level.flood_fill(from, |pos| !blocked(pos));
+    let mut wall = vec![false; level.width * level.height];
+    for &pos in walls.iter() {
+        wall[index(pos)] = true;
+    }
+
+    loop {
+        let reached = level.flood_fill(from, |pos| !blocked(pos) && !wall[index(pos)]);
+        let cut_off = |pos: GridPos| open[index(pos)] && !reached[index(pos)] && !wall[index(pos)];
+        if !walls.iter().flat_map(|&pos| level.neighbors(pos)).any(cut_off) {
+            return walls;
+        }
+        let borders = |pos: &GridPos| level.neighbors(*pos).any(|n| reached[index(n)]);
+        // prefer a door straight into a cut off area, any wall on the edge of
+        // the reached area at least grows it by one cell
+        let door = walls.iter().position(|pos| borders(pos) && level.neighbors(*pos).any(cut_off))
+            .or_else(|| walls.iter().position(borders));
+        let Some(door) = door else {
+            return walls;
+        };
+        wall[index(walls.remove(door))] = false;
+    }
+}
+
+/// Wall lines every `ROOM_SIZE` cells with a door into each neighbouring room
+fn rooms<R: Rng + ?Sized>(wall: &mut [bool], width: usize, height: usize, rng: &mut R) {
+    let (w, h) = (width as i32, height as i32);
+    let index = |x: i32, z: i32| x as usize * height + z as usize;
+    // no wall right next to the border, it would only make a dead end corridor
+    let columns: Vec<i32> = (1..).map(|k| k * ROOM_SIZE).take_while(|&x| x < w - 2).collect();
+    let rows: Vec<i32> = (1..).map(|k| k * ROOM_SIZE).take_while(|&z| z < h - 2).collect();
+    for &x in columns.iter() {
+        for z in 0..h {
+            wall[index(x, z)] = true;
+        }
+    }
+    for &z in rows.iter() {
+        for x in 0..w {
+            wall[index(x, z)] = true;
+        }
+    }
+
+    // one door per wall segment between two crossings
+    let spans = |lines: &[i32], len: i32| -> Vec<(i32, i32)> {
+        let mut bounds = vec![-1];
+        bounds.extend_from_slice(lines);
+        bounds.push(len);
+        bounds.windows(2).map(|b| (b[0] + 1, b[1])).collect()
+    };
+    for &x in columns.iter() {
+        for (z0, z1) in spans(&rows, h) {
+            let door = rng.gen_range(z0..=(z1 - DOOR_WIDTH).max(z0));
+            for z in door..(door + DOOR_WIDTH).min(z1) {
+                wall[index(x, z)] = false;
+            }
+        }
+    }
+    for &z in rows.iter() {
+        for (x0, x1) in spans(&columns, w) {
+            let door = rng.gen_range(x0..=(x1 - DOOR_WIDTH).max(x0));
+            for x in door..(door + DOOR_WIDTH).min(x1) {
+                wall[index(x, z)] = false;
+            }
+        }
+    }
+}
+
+/// Depth first maze on a lattice of 2x2 floor cells split by one cell walls
+fn maze<R: Rng + ?Sized>(wall: &mut [bool], width: usize, height: usize, rng: &mut R) {
+    let (w, h) = (width as i32, height as i32);
+    let index = |x: i32, z: i32| x as usize * height + z as usize;
+    for x in 0..w {
+        for z in 0..h {
+            wall[index(x, z)] = x % 3 == 2 || z % 3 == 2;
+        }
+    }
+
+    // maze cell (i, j) covers the floor at x 3i..3i+2, z 3j..3j+2
+    let (cols, rows) = ((w + 2) / 3, (h + 2) / 3);
+    let open_between = |wall: &mut [bool], (i, j): (i32, i32), (k, l): (i32, i32)| {
+        let (x0, z0) = (3 * i.min(k), 3 * j.min(l));
+        let cells: Vec<(i32, i32)> = if i != k {
+            (z0..z0 + 2).map(|z| (x0 + 2, z)).collect()
+        } else {
+            (x0..x0 + 2).map(|x| (x, z0 + 2)).collect()
+        };
+        for (x, z) in cells {
+            if x < w && z < h {
+                wall[index(x, z)] = false;
+            }
+        }
+    };
+
+    let mut visited = vec![false; (cols * rows) as usize];
+    let mut stack = vec![(rng.gen_range(0..cols), rng.gen_range(0..rows))];
+    visited[(stack[0].0 * rows + stack[0].1) as usize] = true;
+    while let Some(&(i, j)) = stack.last() {
+        let mut next: Vec<(i32, i32)> = [(i + 1, j), (i - 1, j), (i, j + 1), (i, j - 1)]
+            .into_iter()
+            .filter(|&(k, l)| k >= 0 && l >= 0 && k < cols && l < rows && !visited[(k * rows + l) as usize])
+            .collect();
+        next.shuffle(rng);
+        match next.first() {
+            Some(&cell) => {
+                visited[(cell.0 * rows + cell.1) as usize] = true;
+                open_between(wall, (i, j), cell);
+                stack.push(cell);
+            }
+            None => {
+                stack.pop();
+            }
+        }
+    }
+
+    for i in 0..cols {
+        for j in 0..rows {
+            for neighbor in [(i + 1, j), (i, j + 1)] {
+                if neighbor.0 < cols && neighbor.1 < rows && rng.gen_bool(MAZE_LOOP_CHANCE) {
+                    open_between(wall, (i, j), neighbor);
+                }
+            }
+        }
+    }
+}
+
+/// Isolated wall cells, none touching another even diagonally
+fn pillars<R: Rng + ?Sized>(wall: &mut [bool], width: usize, height: usize, rng: &mut R) {
+    let (w, h) = (width as i32, height as i32);
+    let index = |x: i32, z: i32| x as usize * height + z as usize;
+    let mut cells: Vec<(i32, i32)> = (0..w).flat_map(|x| (0..h).map(move |z| (x, z))).collect();
+    cells.shuffle(rng);
+    let mut count = width * height / PILLAR_SPACING;
+    for (x, z) in cells {
+        if count == 0 {
+            break;
+        }
+        let crowded = (x - 1..=x + 1)
+            .flat_map(|nx| (z - 1..=z + 1).map(move |nz| (nx, nz)))
+            .any(|(nx, nz)| nx >= 0 && nz >= 0 && nx < w && nz < h && wall[index(nx, nz)]);
+        if !crowded {
+            wall[index(x, z)] = true;
+            count -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::GameRng;
+
+    #[test]
+    fn generated_levels_are_valid() {
+        for layout in Layout::ALL {
+            for (width, height) in [(8, 8), (25, 25), (40, 25)] {
+                for seed in 0..40 {
+                    let level = generate_level(layout, width, height, &mut GameRng::new(seed));
+                    if let Err(err) = level.validate() {
+                        panic!("{layout} {width}x{height} seed {seed}: {err}");
+                    }
+                }
+            }
+        }
+    }
+}
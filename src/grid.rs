@@ -0,0 +1,48 @@
+//! Integer cell coordinates of the arena.
+
+use std::ops::{Add, Sub};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A cell on the arena floor. Signed so that positions past the edges stay
+/// representable and bounds checks are exact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GridPos {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl GridPos {
+    pub const fn new(x: i32, z: i32) -> Self {
+        Self { x, z }
+    }
+
+    /// Center of the cell in world space, on the floor plane
+    pub fn to_world(self) -> Vec3 {
+        Vec3::new(self.x as f32, 0.0, self.z as f32)
+    }
+
+    /// Cell containing a world position
+    pub fn from_world(pos: Vec3) -> Self {
+        Self::new(pos.x.round() as i32, pos.z.round() as i32)
+    }
+
+    pub fn in_bounds(self, width: usize, height: usize) -> bool {
+        self.x >= 0 && self.z >= 0 && (self.x as usize) < width && (self.z as usize) < height
+    }
+}
+
+impl Add for GridPos {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.z + rhs.z)
+    }
+}
+
+impl Sub for GridPos {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.z - rhs.z)
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{input::InputSystem, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{arg_value, player::Direction};
+
+/// Bindings file read at startup unless `--bindings <path>` is given
+const BINDINGS_FILE: &str = "bindings.ron";
+/// Stick deflection that counts as a press
+const STICK_THRESHOLD: f32 = 0.5;
+
+pub struct InputPlugin;
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(load_bindings())
+            .init_resource::<ActionState>()
+            .add_systems(PreUpdate, update_action_state.after(InputSystem));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    TurnUp,
+    TurnDown,
+    TurnLeft,
+    TurnRight,
+    /// Second player's turns in versus, alone they steer like the others
+    P2TurnUp,
+    P2TurnDown,
+    P2TurnLeft,
+    P2TurnRight,
+    Pause,
+    Confirm,
+    Back,
+}
+
+impl Action {
+    pub const TURNS: [Action; 4] = [Action::TurnLeft, Action::TurnRight, Action::TurnUp, Action::TurnDown];
+    pub const P2_TURNS: [Action; 4] = [Action::P2TurnLeft, Action::P2TurnRight, Action::P2TurnUp, Action::P2TurnDown];
+    pub const ALL_TURNS: [Action; 8] = [
+        Action::TurnLeft, Action::TurnRight, Action::TurnUp, Action::TurnDown,
+        Action::P2TurnLeft, Action::P2TurnRight, Action::P2TurnUp, Action::P2TurnDown,
+    ];
+
+    /// Turns steering `player` out of `players`, a lone player has all of them
+    pub fn turns_of(player: usize, players: usize) -> &'static [Action] {
+        match (player, players) {
+            (_, 1) => &Self::ALL_TURNS,
+            (0, _) => &Self::TURNS,
+            _ => &Self::P2_TURNS,
+        }
+    }
+
+    /// Heading of a turn action
+    pub fn direction(&self) -> Option<Direction> {
+        match self {
+            Action::TurnUp | Action::P2TurnUp => Some(Direction::Up),
+            Action::TurnDown | Action::P2TurnDown => Some(Direction::Down),
+            Action::TurnLeft | Action::P2TurnLeft => Some(Direction::Left),
+            Action::TurnRight | Action::P2TurnRight => Some(Direction::Right),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Button(GamepadButton),
+    /// Stick pushed past `STICK_THRESHOLD` towards the positive or negative end
+    Axis(GamepadAxis, bool),
+}
+
+impl Binding {
+    fn is_pressed(&self, keyboard: &ButtonInput<KeyCode>, gamepads: &Query<&Gamepad>) -> bool {
+        match *self {
+            Binding::Key(key) => keyboard.pressed(key),
+            Binding::Button(button) => gamepads.iter().any(|pad| pad.pressed(button)),
+            Binding::Axis(axis, positive) => gamepads.iter().any(|pad| {
+                let value = pad.get(axis).unwrap_or(0.0);
+                if positive { value > STICK_THRESHOLD } else { value < -STICK_THRESHOLD }
+            }),
+        }
+    }
+}
+
+/// Inputs bound to each action, loaded from a RON file
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct InputBindings(pub HashMap<Action, Vec<Binding>>);
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use Binding::*;
+        Self(HashMap::from([
+            (Action::TurnUp, vec![
+                Key(KeyCode::KeyW),
+                Button(GamepadButton::DPadUp), Axis(GamepadAxis::LeftStickY, true),
+            ]),
+            (Action::TurnDown, vec![
+                Key(KeyCode::KeyS),
+                Button(GamepadButton::DPadDown), Axis(GamepadAxis::LeftStickY, false),
+            ]),
+            (Action::TurnLeft, vec![
+                Key(KeyCode::KeyA),
+                Button(GamepadButton::DPadLeft), Axis(GamepadAxis::LeftStickX, false),
+            ]),
+            (Action::TurnRight, vec![
+                Key(KeyCode::KeyD),
+                Button(GamepadButton::DPadRight), Axis(GamepadAxis::LeftStickX, true),
+            ]),
+            (Action::P2TurnUp, vec![Key(KeyCode::ArrowUp)]),
+            (Action::P2TurnDown, vec![Key(KeyCode::ArrowDown)]),
+            (Action::P2TurnLeft, vec![Key(KeyCode::ArrowLeft)]),
+            (Action::P2TurnRight, vec![Key(KeyCode::ArrowRight)]),
+            (Action::Pause, vec![Key(KeyCode::Escape), Key(KeyCode::KeyP), Button(GamepadButton::Start)]),
+            (Action::Confirm, vec![Key(KeyCode::Enter), Key(KeyCode::Space), Button(GamepadButton::South)]),
+            (Action::Back, vec![Key(KeyCode::Escape), Key(KeyCode::Backspace), Button(GamepadButton::East)]),
+        ]))
+    }
+}
+
+/// Actions held this frame, the rest of the game reads input from here
+#[derive(Resource, Default)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    /// Whether any turn towards `direction` was just pressed, by either player
+    pub fn just_turned(&self, direction: Direction) -> bool {
+        Action::ALL_TURNS.iter().any(|&action| action.direction() == Some(direction) && self.just_pressed(action))
+    }
+}
+
+/// Read `bindings.ron` or the file given with `--bindings <path>`, actions
+/// missing from the file keep their default bindings
+fn load_bindings() -> InputBindings {
+    let mut bindings = InputBindings::default();
+    let explicit = arg_value("--bindings");
+    let path = explicit.clone().unwrap_or_else(|| BINDINGS_FILE.to_string());
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            if explicit.is_some() {
+                warn!("could not read {path}: {err}");
+            }
+            return bindings;
+        }
+    };
+    match ron::from_str::<InputBindings>(&text) {
+        Ok(loaded) => bindings.0.extend(loaded.0),
+        Err(err) => warn!("could not parse {path}: {err}"),
+    }
+    bindings
+}
+
+fn update_action_state(
+    bindings:       Res<InputBindings>,
+    keyboard:       Res<ButtonInput<KeyCode>>,
+    gamepads:       Query<&Gamepad>,
+    mut state:      ResMut<ActionState>,
+) {
+    let pressed: HashSet<Action> = bindings.0.iter()
+        .filter(|(_, inputs)| inputs.iter().any(|input| input.is_pressed(&keyboard, &gamepads)))
+        .map(|(&action, _)| action)
+        .collect();
+    state.just_pressed = pressed.difference(&state.pressed).copied().collect();
+    state.pressed = pressed;
+}
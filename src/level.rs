@@ -0,0 +1,667 @@
+//! Arena descriptions: size, fixed walls and holes, where the snake starts,
+//! the first food and when obstacles rise. Stored as RON `.level.ron` files.
+//!
+//! Arenas with several height levels stack their layers in the grid: layer
+//! `n` is the band of rows `n * depth..(n + 1) * depth`, where `depth` is the
+//! height divided by the number of layers. Ramps climb from one to the next.
+
+use std::{fmt, fs, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{generate::Layout, grid::GridPos, sim::{Direction, MAP_CHANGE_INTERVAL}, surface::{CubeNet, Surface}};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ObstacleSchedule {
+    /// A fresh set of random cells every `interval` seconds
+    Random { interval: f32 },
+    /// Cells raised at fixed game times, each wave replaces the walls of the one before
+    Waves(Vec<ObstacleWave>),
+    /// A generated layout every `interval` seconds, trimmed so it never cuts off any floor
+    Generated { interval: f32, layout: Layout },
+    Off,
+}
+
+impl Default for ObstacleSchedule {
+    fn default() -> Self {
+        ObstacleSchedule::Random { interval: MAP_CHANGE_INTERVAL }
+    }
+}
+
+/// A stair on one layer, walking along `direction` over it climbs onto the
+/// next layer and walking back down it returns. The cell above it stays open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ramp {
+    pub at: GridPos,
+    pub direction: Direction,
+}
+
+/// World height between two layers
+pub const LAYER_RISE: f32 = 2.0;
+
+/// A block walking a path of neighbouring cells back and forth, one cell
+/// every `interval` seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Patrol {
+    pub path: Vec<GridPos>,
+    pub interval: f32,
+}
+
+impl Patrol {
+    /// Cell after `steps` moves
+    pub fn cell(&self, steps: usize) -> GridPos {
+        let n = self.path.len();
+        if n < 2 {
+            return self.path[0];
+        }
+        let i = steps % (2 * n - 2);
+        self.path[if i < n { i } else { 2 * n - 2 - i }]
+    }
+}
+
+/// An arm of `length` cells turning around a fixed pivot, an eighth of a turn
+/// every `interval` seconds starting straight up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sweeper {
+    pub pivot: GridPos,
+    pub length: usize,
+    pub interval: f32,
+    #[serde(default)]
+    pub counter_clockwise: bool,
+}
+
+impl Sweeper {
+    fn heading(&self, steps: usize) -> (i32, i32) {
+        const HEADINGS: [(i32, i32); 8] = [(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)];
+        let turn = if self.counter_clockwise { 8 - steps % 8 } else { steps % 8 };
+        HEADINGS[turn % 8]
+    }
+
+    /// Pivot first, then the arm outwards after `steps` turns
+    pub fn cells(&self, steps: usize) -> impl Iterator<Item = GridPos> + '_ {
+        let (x, z) = self.heading(steps);
+        (0..=self.length as i32).map(move |k| self.pivot + GridPos::new(k * x, k * z))
+    }
+
+    /// Cells the arm passes over on its way from turn `steps` to the next,
+    /// both ends included
+    pub fn swept(&self, steps: usize) -> impl Iterator<Item = GridPos> + '_ {
+        let (from, to) = (self.heading(steps), self.heading(steps + 1));
+        let (dx, dz) = (to.0 - from.0, to.1 - from.1);
+        // the cells k away from the pivot lie on a square ring, and one eighth
+        // of a turn walks one side of it
+        (0..=self.length as i32).flat_map(move |k| {
+            (0..=k).map(move |j| self.pivot + GridPos::new(k * from.0 + j * dx, k * from.1 + j * dz))
+        })
+    }
+
+    /// Every cell the arm passes over
+    pub fn area(&self) -> impl Iterator<Item = GridPos> + '_ {
+        (0..8).flat_map(|steps| self.cells(steps).collect::<Vec<_>>())
+    }
+}
+
+/// A belt that pushes a snake standing on it along `direction`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conveyor {
+    pub at: GridPos,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObstacleWave {
+    /// Game time in seconds
+    pub at: f32,
+    pub cells: Vec<GridPos>,
+}
+
+/// Everything needed to set up an arena, fields other than size and start may be left out
+#[derive(Asset, TypePath, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    #[serde(default)]
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub start: GridPos,
+    pub direction: Direction,
+    /// Cells that are always walls
+    #[serde(default)]
+    pub walls: Vec<GridPos>,
+    /// Cells without floor, the snake dies there like on a wall
+    #[serde(default)]
+    pub holes: Vec<GridPos>,
+    /// Food placed at the start, random food is added up to `food_count`
+    #[serde(default)]
+    pub foods: Vec<GridPos>,
+    /// Cells new food appears on while one of them is free, before any other floor
+    #[serde(default)]
+    pub spawners: Vec<GridPos>,
+    /// Foods lying in the arena at once, eaten and expired ones are replaced
+    #[serde(default = "one")]
+    pub food_count: usize,
+    #[serde(default)]
+    pub obstacles: ObstacleSchedule,
+    /// Leaving one edge enters from the opposite one instead of ending the game
+    #[serde(default)]
+    pub wrap: bool,
+    /// A cube level is the unfolded net of the cube, see `surface`
+    #[serde(default)]
+    pub surface: Surface,
+    /// Height levels stacked in the grid, see the module docs
+    #[serde(default = "one")]
+    pub layers: usize,
+    #[serde(default)]
+    pub ramps: Vec<Ramp>,
+    /// Linked cells, stepping onto one end comes out of the other
+    #[serde(default)]
+    pub portals: Vec<(GridPos, GridPos)>,
+    #[serde(default)]
+    pub patrols: Vec<Patrol>,
+    #[serde(default)]
+    pub sweepers: Vec<Sweeper>,
+    #[serde(default)]
+    pub conveyors: Vec<Conveyor>,
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::Io(err) => write!(f, "level io error: {err}"),
+            LevelError::Parse(err) => write!(f, "invalid level: {err}"),
+            LevelError::Serialize(err) => write!(f, "could not write level: {err}"),
+            LevelError::Invalid(reason) => write!(f, "invalid level: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl From<std::io::Error> for LevelError {
+    fn from(err: std::io::Error) -> Self {
+        LevelError::Io(err)
+    }
+}
+
+impl Level {
+    /// An empty arena with random obstacles, starting in the middle heading up
+    pub fn open(width: usize, height: usize) -> Self {
+        Self {
+            name: String::new(),
+            width,
+            height,
+            start: GridPos::new((width / 2) as i32, (height / 2) as i32),
+            direction: Direction::Up,
+            walls: Vec::new(),
+            holes: Vec::new(),
+            foods: Vec::new(),
+            spawners: Vec::new(),
+            food_count: 1,
+            obstacles: ObstacleSchedule::default(),
+            wrap: false,
+            surface: Surface::Plane,
+            layers: 1,
+            ramps: Vec::new(),
+            portals: Vec::new(),
+            patrols: Vec::new(),
+            sweepers: Vec::new(),
+            conveyors: Vec::new(),
+        }
+    }
+
+    /// Rows per layer
+    pub fn layer_depth(&self) -> usize {
+        self.height / self.layers.max(1)
+    }
+
+    pub fn layer_of(&self, pos: GridPos) -> usize {
+        pos.z.max(0) as usize / self.layer_depth().max(1)
+    }
+
+    /// The same spot one layer up
+    pub fn above(&self, pos: GridPos) -> Option<GridPos> {
+        (self.layer_of(pos) + 1 < self.layers).then(|| pos + GridPos::new(0, self.layer_depth() as i32))
+    }
+
+    fn ramp_at(&self, pos: GridPos) -> Option<&Ramp> {
+        self.ramps.iter().find(|ramp| ramp.at == pos)
+    }
+
+    /// Whether a ramp climbs through this cell from the layer below
+    pub fn over_ramp(&self, pos: GridPos) -> bool {
+        self.ramps.iter().any(|ramp| self.above(ramp.at) == Some(pos))
+    }
+
+    /// A cell off the edge of `layer` brought back in from the far side on a
+    /// wrapping level, anything else as it is
+    pub fn wrapped(&self, pos: GridPos, layer: usize) -> GridPos {
+        if !self.wrap {
+            return pos;
+        }
+        let (depth, top) = (self.layer_depth() as i32, (layer * self.layer_depth()) as i32);
+        GridPos::new(pos.x.rem_euclid(self.width as i32), top + (pos.z - top).rem_euclid(depth))
+    }
+
+    /// Whether a step from `pos` goes over a wrapping edge
+    pub fn wraps(&self, pos: GridPos, direction: Direction) -> bool {
+        if !self.wrap || self.cube_net().is_some() || self.ramp_at(pos).is_some_and(|ramp| ramp.direction == direction) {
+            return false;
+        }
+        let next = pos + direction.offset();
+        !next.in_bounds(self.width, self.height) || self.layer_of(next) != self.layer_of(pos)
+    }
+
+    /// The other end of the portal on `pos`
+    pub fn portal_exit(&self, pos: GridPos) -> Option<GridPos> {
+        self.portals.iter().find_map(|&(a, b)| match pos {
+            _ if pos == a => Some(b),
+            _ if pos == b => Some(a),
+            _ => None,
+        })
+    }
+
+    pub fn conveyor_at(&self, pos: GridPos) -> Option<Direction> {
+        self.conveyors.iter().find(|conveyor| conveyor.at == pos).map(|conveyor| conveyor.direction)
+    }
+
+    /// Cells that patrols and sweeper arms can ever cover
+    pub fn tracks(&self) -> Vec<GridPos> {
+        let mut cells: Vec<GridPos> = self.patrols.iter().flat_map(|patrol| patrol.path.iter().copied())
+            .chain(self.sweepers.iter().flat_map(|sweeper| sweeper.area()))
+            .filter(|&pos| self.contains(pos))
+            .collect();
+        cells.sort_by_key(|pos| (pos.x, pos.z));
+        cells.dedup();
+        cells
+    }
+
+    /// Where `count` snakes start and their headings: the level's start first,
+    /// then each one on free floor far from those before it, though no farther
+    /// than a quarter of the way around the arena, nearer the middle, facing
+    /// its longest straight run. Floor without room for a run of two cells is
+    /// only taken when there is nothing else
+    pub fn starts(&self, count: usize) -> Vec<(GridPos, Direction)> {
+        let tracks = self.tracks();
+        let plain = |pos: GridPos| self.contains(pos) && !self.walls.contains(&pos) && !self.holes.contains(&pos)
+            && !self.foods.contains(&pos) && !tracks.contains(&pos) && self.portal_exit(pos).is_none()
+            && self.conveyor_at(pos).is_none() && self.ramp_at(pos).is_none() && !self.over_ramp(pos);
+        let spread = (self.width + self.height) as i32 / 4;
+        let middle = GridPos::new(self.width as i32 / 2, self.height as i32 / 2);
+        let mut starts = vec![(self.start, self.direction)];
+        while starts.len() < count {
+            let free = |pos: GridPos| plain(pos) && starts.iter().all(|&(start, _)| start != pos);
+            let run = |pos: GridPos, direction: Direction| {
+                let mut cell = (pos, direction);
+                (0..4).take_while(|_| match self.walk(cell.0, cell.1) {
+                    Some(next) if free(next.0) => { cell = next; true }
+                    _ => false,
+                }).count()
+            };
+            let mut best: Option<((bool, i32, usize, i32), GridPos, Direction)> = None;
+            for x in 0..self.width as i32 {
+                for z in 0..self.height as i32 {
+                    let pos = GridPos::new(x, z);
+                    if !free(pos) {
+                        continue;
+                    }
+                    let Some((length, direction)) = Direction::ALL.into_iter()
+                        .map(|direction| (run(pos, direction), direction))
+                        .rev()
+                        .max_by_key(|&(length, _)| length) else {
+                        continue;
+                    };
+                    let apart = |a: GridPos, b: GridPos| (a.x - b.x).abs() + (a.z - b.z).abs();
+                    let distance = starts.iter().map(|&(start, _)| apart(start, pos)).min().unwrap_or(0);
+                    let rank = (length >= 2, distance.min(spread), length, -apart(middle, pos));
+                    if best.is_none_or(|(best_rank, ..)| rank > best_rank) {
+                        best = Some((rank, pos, direction));
+                    }
+                }
+            }
+            // an arena without a single free cell left stacks it on the first
+            starts.push(best.map_or((self.start, self.direction), |(_, pos, direction)| (pos, direction)));
+        }
+        starts
+    }
+
+    /// The cell one step from `pos` and the heading there, `None` when the step
+    /// leaves the arena. Steps wrap on a wrapping level, go over the edges of a
+    /// cube and up and down ramps, and come out of the far end of a portal with
+    /// the same heading. Stairs are only walked along, not across.
+    pub fn step(&self, pos: GridPos, direction: Direction) -> Option<(GridPos, Direction)> {
+        let (next, heading) = self.walk(pos, direction)?;
+        Some((self.portal_exit(next).unwrap_or(next), heading))
+    }
+
+    fn walk(&self, pos: GridPos, direction: Direction) -> Option<(GridPos, Direction)> {
+        if let Some(net) = self.cube_net() {
+            return Some(net.step(pos, direction));
+        }
+        if let Some(ramp) = self.ramp_at(pos) {
+            if direction == ramp.direction {
+                return Some((self.above(pos)? + direction.offset(), direction));
+            }
+            if direction != ramp.direction.opposite() {
+                return None;
+            }
+        }
+        let layer = self.layer_of(pos);
+        let next = self.wrapped(pos + direction.offset(), layer);
+        if !next.in_bounds(self.width, self.height) || self.layer_of(next) != layer {
+            return None;
+        }
+        if let Some(ramp) = self.ramp_at(next) {
+            return (direction == ramp.direction).then_some((next, direction));
+        }
+        // walking off a landing down the stair below it
+        if let Some(ramp) = self.ramps.iter().find(|ramp| self.above(ramp.at) == Some(next)) {
+            if direction == ramp.direction.opposite() {
+                return Some((ramp.at, direction));
+            }
+        }
+        Some((next, direction))
+    }
+
+    /// Whether a step from `pos` runs into the side or the top end of a ramp,
+    /// or off a ramp sideways, which `step` refuses
+    pub fn hits_ramp(&self, pos: GridPos, direction: Direction) -> bool {
+        if self.ramp_at(pos).is_some_and(|ramp| direction != ramp.direction && direction != ramp.direction.opposite()) {
+            return true;
+        }
+        let next = self.wrapped(pos + direction.offset(), self.layer_of(pos));
+        self.cube_net().is_none() && self.ramp_at(next).is_some_and(|ramp| direction != ramp.direction)
+    }
+
+    /// Cells one step away in each direction
+    pub fn neighbors(&self, pos: GridPos) -> impl Iterator<Item = GridPos> + '_ {
+        Direction::ALL.into_iter().filter_map(move |direction| self.step(pos, direction).map(|(next, _)| next))
+    }
+
+    /// Cells reachable from `from` through `passable` cells, as an x-major mask
+    /// like the simulation's cells. Empty when `from` itself is not passable.
+    pub fn flood_fill(&self, from: GridPos, passable: impl Fn(GridPos) -> bool) -> Vec<bool> {
+        let mut reached = vec![false; self.width * self.height];
+        if !self.contains(from) || !passable(from) {
+            return reached;
+        }
+        let index = |pos: GridPos| pos.x as usize * self.height + pos.z as usize;
+        reached[index(from)] = true;
+        let mut stack = vec![from];
+        while let Some(pos) = stack.pop() {
+            for next in self.neighbors(pos) {
+                if !reached[index(next)] && passable(next) {
+                    reached[index(next)] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        reached
+    }
+
+    /// The level laid out flat: the net of a cube or the layers side by side,
+    /// with the cells off the arena and over ramps as holes
+    pub fn unfolded(&self) -> Level {
+        let mut flat = Level {
+            wrap: false,
+            surface: Surface::Plane,
+            layers: 1,
+            ramps: Vec::new(),
+            ..self.clone()
+        };
+        for x in 0..self.width as i32 {
+            for z in 0..self.height as i32 {
+                let pos = GridPos::new(x, z);
+                if !self.contains(pos) || self.over_ramp(pos) {
+                    flat.holes.push(pos);
+                }
+            }
+        }
+        flat
+    }
+
+    /// An empty cube with faces of `size` cells, starting in the middle of the front face
+    pub fn cube(size: usize) -> Self {
+        let net = CubeNet::new(size);
+        let (width, height) = net.net_size();
+        Self {
+            start: GridPos::new((size + size / 2) as i32, (size + size / 2) as i32),
+            surface: Surface::Cube,
+            ..Self::open(width, height)
+        }
+    }
+
+    pub fn cube_net(&self) -> Option<CubeNet> {
+        (self.surface == Surface::Cube).then(|| CubeNet::new(self.width / 4))
+    }
+
+    /// Whether a cell is part of the arena, off the net of a cube it is not
+    pub fn contains(&self, pos: GridPos) -> bool {
+        match self.cube_net() {
+            Some(net) => net.contains(pos),
+            None => pos.in_bounds(self.width, self.height),
+        }
+    }
+
+    /// Where the snake stands on a cell in world space, halfway up on a ramp
+    pub fn cell_world(&self, pos: GridPos) -> Vec3 {
+        if let Some(net) = self.cube_net() {
+            return net.world(pos);
+        }
+        let layer = self.layer_of(pos);
+        let ramp = if self.ramp_at(pos).is_some() { 0.5 } else { 0.0 };
+        Vec3::new(
+            pos.x as f32,
+            (layer as f32 + ramp) * LAYER_RISE,
+            (pos.z - (layer * self.layer_depth()) as i32) as f32,
+        )
+    }
+
+    /// Up on a cell, walls rise and the camera looks down along it
+    pub fn cell_normal(&self, pos: GridPos) -> Vec3 {
+        self.cube_net().map_or(Vec3::Y, |net| net.normal(pos))
+    }
+
+    /// World direction of a heading on a cell
+    pub fn direction_world(&self, pos: GridPos, direction: Direction) -> Vec3 {
+        self.cube_net().map_or(direction.norm(), |net| net.direction(pos, direction))
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, LevelError> {
+        let level: Level = ron::from_str(text).map_err(LevelError::Parse)?;
+        level.validate()?;
+        Ok(level)
+    }
+
+    pub fn to_ron(&self) -> Result<String, LevelError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(LevelError::Serialize)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), LevelError> {
+        self.validate()?;
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        Self::from_ron(&fs::read_to_string(path)?)
+    }
+
+    /// Check that every cell is inside the arena and the start and food cells are open floor
+    pub fn validate(&self) -> Result<(), LevelError> {
+        let invalid = |reason: String| Err(LevelError::Invalid(reason));
+        if self.width == 0 || self.height == 0 {
+            return invalid(format!("empty arena {}x{}", self.width, self.height));
+        }
+        let waves = match &self.obstacles {
+            ObstacleSchedule::Waves(waves) => waves.as_slice(),
+            _ => &[],
+        };
+        let cells = self.walls.iter().chain(&self.holes).chain(&self.foods).chain(&self.spawners)
+            .chain(waves.iter().flat_map(|wave| &wave.cells))
+            .chain(std::iter::once(&self.start));
+        if let Some(net) = self.cube_net() {
+            if net.size() < 2 || net.net_size() != (self.width, self.height) {
+                return invalid(format!("a cube net is 4x3 faces, not {}x{}", self.width, self.height));
+            }
+        }
+        if self.layers == 0 || self.height % self.layers != 0 {
+            return invalid(format!("{} rows do not split into {} layers", self.height, self.layers));
+        }
+        for ramp in self.ramps.iter() {
+            let landing = self.above(ramp.at).map(|above| above + ramp.direction.offset());
+            let on_layer = landing.is_some_and(|landing| landing.in_bounds(self.width, self.height)
+                && self.layer_of(landing) == self.layer_of(ramp.at) + 1);
+            if !ramp.at.in_bounds(self.width, self.height) || !on_layer {
+                return invalid(format!("ramp at ({}, {}) does not lead onto a layer above", ramp.at.x, ramp.at.z));
+            }
+        }
+        let ends: Vec<GridPos> = self.portals.iter().flat_map(|&(a, b)| [a, b]).collect();
+        for (i, end) in ends.iter().enumerate() {
+            let taken = ends[..i].contains(end) || self.walls.contains(end) || self.holes.contains(end)
+                || self.foods.contains(end) || self.spawners.contains(end) || *end == self.start
+                || self.ramp_at(*end).is_some() || self.over_ramp(*end);
+            if !self.contains(*end) || taken {
+                return invalid(format!("portal at ({}, {}) is not on free floor", end.x, end.z));
+            }
+        }
+        for patrol in self.patrols.iter() {
+            let steps_apart = patrol.path.windows(2).all(|pair| {
+                let d = pair[1] - pair[0];
+                d.x.abs() + d.z.abs() == 1
+            });
+            if patrol.path.is_empty() || !steps_apart || patrol.interval <= 0.0 {
+                return invalid("a patrol needs a path of neighbouring cells and a positive interval".to_string());
+            }
+        }
+        if self.sweepers.iter().any(|sweeper| sweeper.length == 0 || sweeper.interval <= 0.0) {
+            return invalid("a sweeper needs an arm and a positive interval".to_string());
+        }
+        let mut covered = self.patrols.iter().flat_map(|patrol| patrol.path.iter().copied())
+            .chain(self.sweepers.iter().flat_map(|sweeper| sweeper.area()));
+        if let Some(pos) = covered.find(|&pos| !self.contains(pos)) {
+            return invalid(format!("moving obstacle at ({}, {}) leaves the arena", pos.x, pos.z));
+        }
+        let tracks = self.tracks();
+        let conveyors = self.conveyors.iter().map(|conveyor| &conveyor.at);
+        for &pos in tracks.iter().chain(conveyors) {
+            let taken = self.walls.contains(&pos) || self.holes.contains(&pos) || self.portal_exit(pos).is_some()
+                || self.ramp_at(pos).is_some() || self.over_ramp(pos);
+            if !self.contains(pos) || taken {
+                return invalid(format!("moving obstacle or conveyor at ({}, {}) is not on free floor", pos.x, pos.z));
+            }
+        }
+        if tracks.contains(&self.start) || tracks.contains(&(self.start + self.direction.offset())) {
+            return invalid("a moving obstacle crosses the snake's start".to_string());
+        }
+        for &pos in cells {
+            if !self.contains(pos) {
+                return invalid(format!("cell ({}, {}) outside the {}x{} arena", pos.x, pos.z, self.width, self.height));
+            }
+        }
+        let blocked = |pos: &GridPos| self.walls.contains(pos) || self.holes.contains(pos);
+        if blocked(&self.start) {
+            return invalid("the snake starts on a wall or hole".to_string());
+        }
+        if let Some(food) = self.foods.iter().chain(&self.spawners).find(|&f| blocked(f) || *f == self.start) {
+            return invalid(format!("food or spawner at ({}, {}) is not on free floor", food.x, food.z));
+        }
+        if self.food_count == 0 {
+            return invalid("at least one food has to be out at a time".to_string());
+        }
+        if let ObstacleSchedule::Random { interval } | ObstacleSchedule::Generated { interval, .. } = self.obstacles {
+            if interval <= 0.0 {
+                return invalid(format!("obstacle interval {interval} must be positive"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::keep_connected;
+
+    /// Cells a flood fill from `from` reaches with `walls` closed
+    fn reached(level: &Level, from: GridPos, walls: &[GridPos]) -> Vec<GridPos> {
+        let mask = level.flood_fill(from, |pos| !walls.contains(&pos));
+        let height = level.height as i32;
+        (0..mask.len() as i32).filter(|&i| mask[i as usize]).map(|i| GridPos::new(i / height, i % height)).collect()
+    }
+
+    #[test]
+    fn moving_obstacles_stay_in_the_arena() {
+        let mut level = Level::open(10, 10);
+        level.start = GridPos::new(2, 8);
+        level.patrols.push(Patrol { path: vec![GridPos::new(1, 1), GridPos::new(0, 1)], interval: 0.5 });
+        level.sweepers.push(Sweeper { pivot: GridPos::new(6, 4), length: 3, interval: 1.0, counter_clockwise: false });
+        level.validate().unwrap();
+        level.patrols[0].path.push(GridPos::new(-1, 1));
+        assert!(level.validate().is_err());
+        level.patrols[0].path.pop();
+        level.sweepers[0].length = 4;
+        assert!(level.validate().is_err());
+    }
+
+    #[test]
+    fn cramped_arenas_still_start_snakes_apart() {
+        // walls everywhere but the first snake's start, the cell ahead of it
+        // and one cell with no room to run
+        let mut level = Level::open(10, 10);
+        let open = [level.start, level.start + level.direction.offset(), GridPos::new(1, 1)];
+        level.walls = (0..10).flat_map(|x| (0..10).map(move |z| GridPos::new(x, z)))
+            .filter(|pos| !open.contains(pos))
+            .collect();
+        let starts = level.starts(3);
+        assert_eq!(starts[0], (level.start, level.direction));
+        assert_eq!(starts[1].0, GridPos::new(1, 1));
+        assert_eq!(starts[2].0, open[1]);
+    }
+
+    #[test]
+    fn flood_fill_goes_over_wrapping_edges() {
+        let mut level = Level::open(8, 8);
+        // a wall down the middle leaves the right half to the wrapping edges
+        let walls: Vec<GridPos> = (0..8).map(|z| GridPos::new(4, z)).collect();
+        assert!(!reached(&level, GridPos::new(1, 1), &walls).contains(&GridPos::new(6, 1)));
+        level.wrap = true;
+        assert!(reached(&level, GridPos::new(1, 1), &walls).contains(&GridPos::new(6, 1)));
+        assert!(level.wraps(GridPos::new(0, 3), Direction::Left));
+        assert!(!level.wraps(GridPos::new(1, 3), Direction::Left));
+        assert_eq!(level.wrapped(GridPos::new(-1, 8), 0), GridPos::new(7, 0));
+    }
+
+    #[test]
+    fn flood_fill_goes_over_the_edges_of_a_cube() {
+        let level = Level::cube(4);
+        // the seam between the top and front faces in the net
+        let seam: Vec<GridPos> = (4..8).map(|x| GridPos::new(x, 3)).collect();
+        let cells = reached(&level, GridPos::new(6, 6), &seam);
+        assert_eq!(cells.len(), 6 * 4 * 4 - seam.len());
+        assert!(cells.iter().all(|&pos| level.contains(pos)));
+        assert!(cells.contains(&GridPos::new(5, 1)));
+        // the top face is still reached the long way round, nothing is cut off
+        assert_eq!(keep_connected(&level, GridPos::new(6, 6), seam.clone(), |_| false), seam);
+
+        // a ring round the middle of the front face closes it in
+        let ring: Vec<GridPos> = (4..8).flat_map(|x| (4..8).map(move |z| GridPos::new(x, z)))
+            .filter(|pos| pos.x == 4 || pos.x == 7 || pos.z == 4 || pos.z == 7)
+            .collect();
+        assert!(keep_connected(&level, GridPos::new(5, 5), ring.clone(), |_| false).len() < ring.len());
+    }
+}
@@ -0,0 +1,99 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, LoadState},
+    prelude::*,
+};
+use snake_3d::{level::{Level, LevelError}, rng::GameRng};
+
+use crate::{arg_value, ArenaConfig, GameState, SeedConfig};
+
+pub struct LevelPlugin;
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<Level>()
+            .register_asset_loader(LevelLoader)
+            .add_systems(OnEnter(GameState::Loading), load_level)
+            .add_systems(Update, finish_loading.run_if(in_state(GameState::Loading)));
+    }
+}
+
+/// The level new games are played on, an arena of `ArenaConfig` size and layout
+/// unless a level file is given with `--level <path>` or `SNAKE_LEVEL`
+#[derive(Resource)]
+pub struct ActiveLevel(pub Level);
+
+impl ActiveLevel {
+    /// Follow camera distance, pulled back on larger arenas to keep more of it in view
+    pub fn camera_offset(&self) -> Vec3 {
+        let scale = (self.0.width.max(self.0.height) as f32 / 25.0).clamp(0.8, 1.6);
+        Vec3::new(0.0, 15.0, 15.0) * scale
+    }
+}
+
+#[derive(Resource)]
+struct LevelHandle(Handle<Level>);
+
+/// Reads `.level.ron` files from the assets folder
+struct LevelLoader;
+impl AssetLoader for LevelLoader {
+    type Asset = Level;
+    type Settings = ();
+    type Error = LevelError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Level, LevelError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let text = String::from_utf8(bytes).map_err(|err| LevelError::Invalid(err.to_string()))?;
+        Level::from_ron(&text)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+fn load_level(
+    mut commands:   Commands,
+    asset_server:   Res<AssetServer>,
+    arena:          Res<ArenaConfig>,
+    seed_config:    Res<SeedConfig>,
+) {
+    // a fixed seed also fixes the generated layout
+    let mut rng = GameRng::new(seed_config.0.unwrap_or_else(rand::random));
+    commands.insert_resource(ActiveLevel(arena.level(&mut rng)));
+    if let Some(path) = arg_value("--level").or_else(|| std::env::var("SNAKE_LEVEL").ok()) {
+        commands.insert_resource(LevelHandle(asset_server.load(path)));
+    }
+}
+
+/// Leave the loading screen once the level file is ready, or fall back to the open arena
+fn finish_loading(
+    mut commands:   Commands,
+    asset_server:   Res<AssetServer>,
+    arena:          Res<ArenaConfig>,
+    levels:         Res<Assets<Level>>,
+    handle:         Option<Res<LevelHandle>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if let Some(handle) = handle {
+        match asset_server.load_state(&handle.0) {
+            LoadState::Loaded => {
+                if let Some(level) = levels.get(&handle.0) {
+                    info!("playing level {:?}", level.name);
+                    let mut level = level.clone();
+                    arena.apply_food_count(&mut level);
+                    commands.insert_resource(ActiveLevel(level));
+                }
+            }
+            LoadState::Failed(err) => warn!("{err}"),
+            _ => return,
+        }
+        commands.remove_resource::<LevelHandle>();
+    }
+    next_state.set(GameState::Menu);
+}
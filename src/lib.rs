@@ -0,0 +1,13 @@
+//! Game logic that runs without a window or renderer.
+
+pub mod ai;
+pub mod env;
+pub mod food;
+pub mod generate;
+pub mod grid;
+pub mod level;
+pub mod sim;
+pub mod rng;
+pub mod replay;
+pub mod scores;
+pub mod surface;
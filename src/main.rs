@@ -1,23 +1,26 @@
 use std::time::Duration;
 
-use bevy::{
-    audio::AudioPlugin, core_pipeline::{bloom::Bloom, tonemapping::Tonemapping}, 
-    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin}, 
-    pbr::{CascadeShadowConfigBuilder, NotShadowCaster}, prelude::*
-};
-use camera::TopdownCamera;
-use game_flow::{MapModifyEvent, SpawnFoodEvent};
+use bevy::{audio::AudioPlugin, prelude::*};
+use camera::{CameraFrame, TopdownCamera};
 use player::*;
+use levels::ActiveLevel;
+use rand::Rng;
+use snake_3d::{food::FoodKind, generate::{generate_level, Layout}, grid::GridPos, level::Level, rng::GameRng, sim::{Direction, SnakeSim}};
 
 mod camera;
+mod editor;
 mod player;
 mod animation;
 mod menu;
 mod game_flow;
+mod input;
+mod levels;
+mod pause;
+mod replay_viewer;
+mod rivals;
 mod utils;
 
 // Size
-const MAP_SIZE: usize   = 25;
 const CUBE_SPACE: f32   = 0.2;
 const HEAD_SIZE: f32    = 0.6;
 const BODY_SIZE: f32    = 0.4;
@@ -29,12 +32,29 @@ const SNAKE_BODY_COLOR: Color = Color::srgb(0.0, 0.39, 1.0);
 const FOOD_COLOR:       Color = Color::srgb(0.0, 0.39, 1.0);
 const RED_COLOR:        Color = Color::srgb(1.0, 0.0, 0.0);
 const GREEN_COLOR:      Color = Color::srgb(0.0, 1.0, 0.0);
+const PORTAL_COLOR:     Color = Color::srgb(0.7, 0.2, 1.0);
+const CONVEYOR_COLOR:   Color = Color::srgb(1.0, 0.75, 0.1);
+const RIVAL_HEAD_COLOR: Color = Color::srgb(1.0, 0.85, 0.0);
+const RIVAL_BODY_COLOR: Color = Color::srgb(0.0, 0.8, 0.3);
+
+fn food_color(kind: FoodKind) -> Color {
+    match kind {
+        FoodKind::Normal => FOOD_COLOR,
+        FoodKind::Golden => Color::srgb(1.0, 0.8, 0.0),
+        FoodKind::Shrink => Color::srgb(1.0, 0.4, 0.7),
+        FoodKind::Slow => Color::srgb(0.3, 1.0, 0.6),
+        FoodKind::Fast => Color::srgb(1.0, 0.35, 0.0),
+        FoodKind::Ghost => Color::srgb(0.85, 0.85, 0.85),
+        FoodKind::Bomb => Color::srgb(0.5, 0.0, 0.1),
+    }
+}
 
 const STATE_TRANSITION_TIME: f32 = 4.0;
 
 
 fn main() {
-    App::new()
+    let mut app = App::new();
+    app
         .add_plugins((
             // DefaultPlugins,
             DefaultPlugins.set(
@@ -46,19 +66,36 @@ fn main() {
                 meta_check: bevy::asset::AssetMetaCheck::Never,
                 ..default()
             }),
+            input::InputPlugin,
+            levels::LevelPlugin,
             camera::CameraPlugin,
             player::PlayerPlugin,
             animation::AnimationPlugin,
             menu::MenuPlugin,
             game_flow::GameFlowPlugin,
+            pause::PausePlugin,
+            editor::EditorPlugin,
+            replay_viewer::ReplayViewerPlugin,
+            rivals::RivalsPlugin,
 
             // FrameTimeDiagnosticsPlugin,
             // LogDiagnosticsPlugin::default(),
-        ))
+        ));
+    // read once logging is set up, bad values are warned about
+    let arena = ArenaConfig::from_env();
+    app
         .init_state::<GameState>()
+        .add_computed_state::<Playing>()
+        .add_computed_state::<Simulating>()
+        .add_sub_state::<PauseState>()
         .init_resource::<MapState>()
+        .insert_resource(SnakeSim::new(&Level::open(arena.width, arena.height)))
+        .insert_resource(arena)
+        .insert_resource(SeedConfig::from_env())
+        .insert_resource(GameRng::from_entropy())
         .add_systems(OnEnter(GameState::Loading), load_assets)
         .add_systems(OnEnter(GameState::Menu), spawn_world)
+        .add_systems(OnEnter(GameState::Restarting), spawn_world)
         .add_systems(Update, (
             change_track,
             fade_in,
@@ -72,7 +109,43 @@ enum GameState {
     Loading,
     Menu,
     InGame,
+    /// Watching a recorded game
+    Replay,
     GameOver,
+    /// One frame between a game and its restart, the world is rebuilt here
+    Restarting,
+    /// Level editor over the menu's world
+    Editor,
+}
+
+/// A game is running, either played live or from a replay
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct Playing;
+impl ComputedStates for Playing {
+    type SourceStates = GameState;
+
+    fn compute(sources: GameState) -> Option<Self> {
+        matches!(sources, GameState::InGame | GameState::Replay).then_some(Playing)
+    }
+}
+
+/// Snakes move on the grid, in a game or in the menu's demo behind the buttons
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct Simulating;
+impl ComputedStates for Simulating {
+    type SourceStates = GameState;
+
+    fn compute(sources: GameState) -> Option<Self> {
+        matches!(sources, GameState::InGame | GameState::Replay | GameState::Menu).then_some(Simulating)
+    }
+}
+
+#[derive(SubStates, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[source(Playing = Playing)]
+enum PauseState {
+    #[default]
+    Running,
+    Paused,
 }
 
 
@@ -97,62 +170,214 @@ pub struct GlobalAssets {
     pub map_cube: Handle<Mesh>,
     pub map_cube_mat: Handle<StandardMaterial>,
     pub map_cube_mat_emission: Handle<StandardMaterial>,
+    pub portal_mat: Handle<StandardMaterial>,
+    pub conveyor_mat: Handle<StandardMaterial>,
     // Snake
     pub snake_head: Handle<Mesh>,
     pub snake_head_mat: Handle<StandardMaterial>,
     pub snake_body: Handle<Mesh>,
     pub snake_body_mat: Handle<StandardMaterial>,
+    /// Head and body of every snake after the first
+    pub rival_head_mat: Handle<StandardMaterial>,
+    pub rival_body_mat: Handle<StandardMaterial>,
     // Food
     pub food: Handle<Mesh>,
     pub food_mat: Handle<StandardMaterial>,
+    /// One per `FoodKind::ALL`
+    pub food_kind_mats: Vec<Handle<StandardMaterial>>,
 
     // Effects
     pub red_mat: Handle<StandardMaterial>,
     pub green_mat: Handle<StandardMaterial>,
 }
 
+/// Value following `name` on the command line
+fn arg_value(name: &str) -> Option<String> {
+    std::env::args()
+        .skip_while(|arg| arg != name)
+        .nth(1)
+}
+
+/// Whether `name` is given on the command line
+fn arg_flag(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
+
+/// Gameplay seed fixed at launch with `--seed <n>` or the `SNAKE_SEED` environment variable
+#[derive(Resource, Default)]
+pub struct SeedConfig(pub Option<u64>);
+impl SeedConfig {
+    fn from_env() -> Self {
+        let seed = arg_value("--seed")
+            .or_else(|| std::env::var("SNAKE_SEED").ok())
+            .and_then(|s| s.trim().parse().ok());
+        Self(seed)
+    }
+}
+
+/// Arena size picked on the menu or at launch with `--arena <width>x<height>` or the `SNAKE_ARENA` environment
+/// variable, an optional generated layout with `--layout <rooms|maze|pillars>` or `SNAKE_LAYOUT`,
+/// and wrapping edges with `--wrap` or `SNAKE_WRAP=1`. `--cube [face size]` or `SNAKE_CUBE=<face size>`
+/// plays on the surface of a cube instead.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ArenaConfig {
+    pub width: usize,
+    pub height: usize,
+    pub layout: Option<Layout>,
+    pub wrap: bool,
+    /// Face size of a cube arena
+    pub cube: Option<usize>,
+    /// Foods out at once, overriding the level's own count
+    pub food_count: Option<usize>,
+}
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self { width: 25, height: 25, layout: None, wrap: false, cube: None, food_count: None }
+    }
+}
+impl ArenaConfig {
+    /// Smallest side that leaves room around the start
+    const MIN_SIZE: usize = 8;
+    /// Cube face size when `--cube` has none
+    const CUBE_SIZE: usize = 10;
+    /// Sizes the menu steps through
+    const MENU_SIZES: [(usize, usize); 4] = [(15, 15), (25, 25), (40, 25), (40, 40)];
+    const MENU_CUBE_SIZES: [usize; 3] = [6, 10, 14];
+
+    fn from_env() -> Self {
+        let mut arena = Self::default();
+        if let Some(value) = arg_value("--arena").or_else(|| std::env::var("SNAKE_ARENA").ok()) {
+            let size = value.trim().split_once('x')
+                .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                .filter(|&(w, h)| w >= Self::MIN_SIZE && h >= Self::MIN_SIZE);
+            match size {
+                Some((width, height)) => (arena.width, arena.height) = (width, height),
+                None => warn!("invalid arena size {value:?}, expected <width>x<height> of at least {}", Self::MIN_SIZE),
+            }
+        }
+        if let Some(value) = arg_value("--layout").or_else(|| std::env::var("SNAKE_LAYOUT").ok()) {
+            match value.parse() {
+                Ok(layout) => arena.layout = Some(layout),
+                Err(err) => warn!("{err}"),
+            }
+        }
+        arena.wrap = arg_flag("--wrap") || std::env::var("SNAKE_WRAP").is_ok_and(|v| v == "1");
+        if arg_flag("--cube") || std::env::var("SNAKE_CUBE").is_ok() {
+            let size = arg_value("--cube").or_else(|| std::env::var("SNAKE_CUBE").ok())
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(Self::CUBE_SIZE);
+            arena.cube = Some(size.max(Self::MIN_SIZE / 2));
+        }
+        if let Some(value) = arg_value("--foods").or_else(|| std::env::var("SNAKE_FOODS").ok()) {
+            match value.trim().parse() {
+                Ok(count) if count > 0 => arena.food_count = Some(count),
+                _ => warn!("invalid food count {value:?}, expected a number of at least 1"),
+            }
+        }
+        arena
+    }
+
+    /// Switch to the size after this one on the menu, back to the first after the last
+    pub fn next_size(&mut self) {
+        if let Some(size) = self.cube.as_mut() {
+            let sizes = Self::MENU_CUBE_SIZES;
+            *size = sizes.into_iter().find(|&s| s > *size).unwrap_or(sizes[0]);
+            return;
+        }
+        let sizes = Self::MENU_SIZES;
+        let next = sizes.iter().position(|&size| size == (self.width, self.height)).map_or(0, |i| (i + 1) % sizes.len());
+        (self.width, self.height) = sizes[next];
+    }
+
+    pub fn size_label(&self) -> String {
+        match self.cube {
+            Some(size) => format!("Cube {size}"),
+            None => format!("{}x{}", self.width, self.height),
+        }
+    }
+
+    /// Arena of this size, open or with the generated layout, or an empty cube
+    pub fn level<R: Rng + ?Sized>(&self, rng: &mut R) -> Level {
+        let mut level = match (self.cube, self.layout) {
+            (Some(size), _) => Level::cube(size),
+            (None, Some(layout)) => generate_level(layout, self.width, self.height, rng),
+            (None, None) => Level::open(self.width, self.height),
+        };
+        level.wrap = self.wrap && self.cube.is_none();
+        self.apply_food_count(&mut level);
+        level
+    }
+
+    /// Use the food count given at launch, if any, for a level
+    pub fn apply_food_count(&self, level: &mut Level) {
+        if let Some(count) = self.food_count {
+            level.food_count = count;
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct MapState {
-    grid: Vec<Entity>,
+    /// Floor cubes, x-major like the simulation, `None` over holes
+    grid: Vec<Option<Entity>>,
+    /// Cells per column of `grid`
+    grid_height: usize,
+    /// The level `grid` was built from, the editor shows its level unfolded
+    floor: Option<Level>,
+    // summary of the last game, shown in the menu
     score: i32,
     time_elapsed: f32,
-    map_change_timer: Timer,
+    seed: u64,
     transition_to_menu_timer: Timer,
 }
-type TilePos = (usize, usize);
 #[derive(Clone, Component)]
 pub struct CubeState {
-    pub pos: TilePos,
+    pub pos: GridPos,
     pub walkable: bool,
+    pub kind: CubeKind,
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CubeKind {
+    Floor,
+    /// One end of a portal
+    Portal,
+    /// A belt pushing the snake along `direction`
+    Conveyor { direction: Direction },
 }
 impl Default for MapState {
     fn default() -> Self {
         Self { 
             grid: Vec::new(), 
+            grid_height: 0,
+            floor: None,
             score: 0, 
             time_elapsed: 0.0,
-            map_change_timer: Timer::from_seconds(5.0, TimerMode::Repeating), 
+            seed: 0,
             transition_to_menu_timer: Timer::from_seconds(STATE_TRANSITION_TIME, TimerMode::Once),
         }
     }
 }
 impl MapState {
-    fn set_grid(&mut self, grid: Vec<Entity>) {
+    fn set_grid(&mut self, grid: Vec<Option<Entity>>, level: &Level) {
         self.grid = grid;
+        self.grid_height = level.height;
+        self.floor = Some(level.clone());
     }
 
-    fn update(
-        mut map_state:  ResMut<MapState>, 
-        time:           Res<Time>,
-        mut ev_writer:  EventWriter<MapModifyEvent>,
-    ) {
-        map_state.time_elapsed += time.delta_secs();
-        map_state.map_change_timer.tick(Duration::from_secs_f32(time.delta_secs()));
-        if map_state.map_change_timer.just_finished() {
-            ev_writer.send(MapModifyEvent{
-                cube_count: 10 + ((map_state.time_elapsed / 20.) as usize).min(25)
-            });
-        }
+    fn index(&self, pos: GridPos) -> Option<usize> {
+        let index = pos.x as usize * self.grid_height + pos.z as usize;
+        (pos.x >= 0 && pos.z >= 0 && (pos.z as usize) < self.grid_height && index < self.grid.len()).then_some(index)
+    }
+
+    /// Cube entity under a cell
+    fn cube(&self, pos: GridPos) -> Option<Entity> {
+        self.index(pos).and_then(|i| self.grid[i])
+    }
+
+    /// Replace the cube under a cell, returning the old one
+    fn set_cube(&mut self, pos: GridPos, cube: Option<Entity>) -> Option<Entity> {
+        let i = self.index(pos)?;
+        std::mem::replace(&mut self.grid[i], cube)
     }
 
     fn update_transition_timer(
@@ -169,12 +394,26 @@ impl MapState {
     }
 }
 
+impl GlobalAssets {
+    pub fn head_material(&self, snake: usize) -> Handle<StandardMaterial> {
+        if snake == 0 { self.snake_head_mat.clone() } else { self.rival_head_mat.clone() }
+    }
+
+    pub fn body_material(&self, snake: usize) -> Handle<StandardMaterial> {
+        if snake == 0 { self.snake_body_mat.clone() } else { self.rival_body_mat.clone() }
+    }
+
+    pub fn food_material(&self, kind: FoodKind) -> Handle<StandardMaterial> {
+        let index = FoodKind::ALL.iter().position(|&k| k == kind).unwrap_or(0);
+        self.food_kind_mats[index].clone()
+    }
+}
+
 fn load_assets(
     mut commands:   Commands,
     asset_server:   Res<AssetServer>,
     mut meshes:     ResMut<Assets<Mesh>>,
     mut materials:  ResMut<Assets<StandardMaterial>>,
-    mut next_state: ResMut<NextState<GameState>>,
 ) {
     // Map
     let map_cube = meshes.add(Cuboid::new(1.0-CUBE_SPACE/2., 1.0-CUBE_SPACE/2., 1.0-CUBE_SPACE/2.));
@@ -183,6 +422,11 @@ fn load_assets(
         emissive: SNAKE_BODY_COLOR.into(),
         ..default()
     });
+    let portal_mat = materials.add(StandardMaterial {
+        base_color: PORTAL_COLOR,
+        emissive: PORTAL_COLOR.into(),
+        ..default()
+    });
     // Snake
     let snake_head = meshes.add(Cuboid::new(HEAD_SIZE, HEAD_SIZE, HEAD_SIZE));
     let snake_head_mat = materials.add(SNAKE_HEAD_COLOR);
@@ -213,39 +457,48 @@ fn load_assets(
         map_cube,
         map_cube_mat,
         map_cube_mat_emission,
+        portal_mat,
+        conveyor_mat: materials.add(CONVEYOR_COLOR),
         snake_head,
         snake_head_mat,
         snake_body,
         snake_body_mat,
+        rival_head_mat: materials.add(RIVAL_HEAD_COLOR),
+        rival_body_mat: materials.add(StandardMaterial {
+            emissive: RIVAL_BODY_COLOR.into(),
+            ..default()
+        }),
         food,
         food_mat,
+        food_kind_mats: FoodKind::ALL.iter().map(|&kind| materials.add(StandardMaterial {
+            emissive: food_color(kind).into(),
+            ..default()
+        })).collect(),
 
         red_mat: materials.add(StandardMaterial {
-            base_color: RED_COLOR.into(),
+            base_color: RED_COLOR,
             emissive: RED_COLOR.into(),
             ..default()
         }),
         green_mat: materials.add(StandardMaterial {
-            base_color: GREEN_COLOR.into(),
+            base_color: GREEN_COLOR,
             emissive: GREEN_COLOR.into(),
             ..default()
         }),
     });
-    
-
-    next_state.set(GameState::Menu);
 }
 
 fn spawn_world(
     mut commands:   Commands,
     game_assets:    Res<GlobalAssets>,
     mut map_state:  ResMut<MapState>,
-    mut spawn_food_event: EventWriter<SpawnFoodEvent>,
+    level:          Res<ActiveLevel>,
     cam_query:      Query<&TopdownCamera>,
 ) {
-    match cam_query.get_single() {
-        Ok(_) => {},
-        Err(_) => {
+    // the views of other players come and go with their games
+    match cam_query.iter().find(|camera| camera.view == 0) {
+        Some(_) => {},
+        None => {
             // Spawn camera follow player
             commands.spawn((
                 Camera3d::default(),
@@ -256,7 +509,7 @@ fn spawn_world(
                 // Tonemapping::TonyMcMapface,
                 // Bloom::NATURAL,
                 Transform::from_xyz(-4.5, 15.5, 19.0).looking_at(Vec3::ZERO, Vec3::Y),
-                TopdownCamera::with_offset(Vec3::new(0.0, 15.0, 15.0)),
+                TopdownCamera::with_offset(level.camera_offset()),
                 // CascadeShadowConfigBuilder {
                 //     num_cascades: 4,
                 //     maximum_distance: 30.0,
@@ -266,32 +519,21 @@ fn spawn_world(
         }
     }
     
-    let mut grid: Vec<Entity> = Vec::new();
-    for i in 0..MAP_SIZE {
-        for j in 0..MAP_SIZE {
-            let cube_state = CubeState {
-                pos: (i, j),
-                walkable: true,
-            };
-            let cube = commands.spawn((
-                cube_state,
-                Mesh3d(game_assets.map_cube.clone()),
-                MeshMaterial3d(game_assets.map_cube_mat.clone()),
-                Transform::from_xyz(i as f32, -1.0, j as f32),
-                // NotShadowCaster,
-            )).id();
-            grid.push(cube);
-        }
-    }
-    map_state.set_grid(grid);
-    
+    let grid = spawn_grid(&mut commands, &game_assets, &level.0);
+    map_state.set_grid(grid, &level.0);
 
     // Spawn player
+    spawn_snake_head(&mut commands, &game_assets, &level.0, Snake::new(level.0.start));
+}
+
+/// Head of a snake with its light, the bodies are added as it grows
+fn spawn_snake_head(commands: &mut Commands, game_assets: &GlobalAssets, level: &Level, snake: Snake) -> Entity {
     commands.spawn((
         Mesh3d(game_assets.snake_head.clone()),
-        MeshMaterial3d(game_assets.snake_head_mat.clone()),
-        Transform::from_xyz((MAP_SIZE as f32 / 2.0).floor(), 0.0, (MAP_SIZE as f32 / 2.0).floor()),
-        Snake::default(),
+        MeshMaterial3d(game_assets.head_material(snake.index)),
+        Transform::from_translation(level.cell_world(snake.cell)),
+        snake,
+        CameraFrame(Quat::IDENTITY),
         // CameraFollowTarget,
     )).with_children(|parent| {
         parent.spawn((
@@ -311,17 +553,55 @@ fn spawn_world(
             },
             Transform::from_xyz(0.0, 10.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
         ));
-    });
-
-    for _ in 0..4 {
-        spawn_food_event.send(SpawnFoodEvent);
-    }
-    
+    }).id()
 }
 
 
 
 
+/// Floor cubes of a level, x-major, see `spawn_cube`
+fn spawn_grid(commands: &mut Commands, game_assets: &GlobalAssets, level: &Level) -> Vec<Option<Entity>> {
+    let mut grid: Vec<Option<Entity>> = Vec::new();
+    for i in 0..level.width {
+        for j in 0..level.height {
+            grid.push(spawn_cube(commands, game_assets, level, GridPos::new(i as i32, j as i32)));
+        }
+    }
+    grid
+}
+
+/// The floor cube of a cell. Walls stand a cube higher, portals glow,
+/// conveyors carry a nub on the side they push to. Holes, cells off the net
+/// of a cube and the openings over ramps have none.
+fn spawn_cube(commands: &mut Commands, game_assets: &GlobalAssets, level: &Level, pos: GridPos) -> Option<Entity> {
+    if level.holes.contains(&pos) || !level.contains(pos) || level.over_ramp(pos) {
+        return None;
+    }
+    let walkable = !level.walls.contains(&pos);
+    let height = if walkable { -1.0 } else { 0.0 };
+    let (kind, material) = match (level.portal_exit(pos), level.conveyor_at(pos)) {
+        (Some(_), _) => (CubeKind::Portal, game_assets.portal_mat.clone()),
+        (_, Some(direction)) => (CubeKind::Conveyor { direction }, game_assets.conveyor_mat.clone()),
+        _ => (CubeKind::Floor, game_assets.map_cube_mat.clone()),
+    };
+    let cube = commands.spawn((
+        Mesh3d(game_assets.map_cube.clone()),
+        MeshMaterial3d(material),
+        Transform::from_translation(level.cell_world(pos) + level.cell_normal(pos) * height),
+        CubeState { pos, walkable, kind },
+        // NotShadowCaster,
+    )).id();
+    if let CubeKind::Conveyor { direction } = kind {
+        let nub = level.cell_normal(pos) * 0.5 + level.direction_world(pos, direction) * 0.25;
+        commands.entity(cube).with_child((
+            Mesh3d(game_assets.snake_body.clone()),
+            MeshMaterial3d(game_assets.green_mat.clone()),
+            Transform::from_translation(nub).with_scale(Vec3::splat(0.5)),
+        ));
+    }
+    Some(cube)
+}
+
 // This component will be attached to an entity to fade the audio in
 #[derive(Component)]
 struct FadeIn;
@@ -357,7 +637,7 @@ fn change_track(
             ));
         
         }
-        GameState::InGame => {
+        GameState::InGame | GameState::Replay => {
             commands.spawn((
                 AudioPlayer(game_assets.ingame_track.clone()),
                 PlaybackSettings {
@@ -1,50 +1,105 @@
-use bevy::{color::palettes::{css::{GREEN, WHITE}, tailwind::{GREEN_100, GREEN_950}}, prelude::*};
-use rand::{thread_rng, Rng};
-use crate::{camera::{CameraFollowTarget, TopdownCamera}, game_flow::Food, utils::format_time, GameState, GlobalAssets, MapState, MAP_SIZE};
+use bevy::{color::palettes::css::WHITE, prelude::*};
+use snake_3d::{ai::{choose_direction, Difficulty}, level::Level, replay::start_game, rng::GameRng, scores::HighScores, sim::{Direction, SnakeSim}};
+use crate::{
+    camera::{CameraFollowTarget, TopdownCamera},
+    game_flow::{spawn_hazards, Food, GameMode, Hazard, SpawnFoodEvent},
+    input::{Action, ActionState},
+    player::{Snake, SnakeBody, SnakeStep},
+    replay_viewer::{LastReplay, Playback},
+    rivals::RivalConfig,
+    levels::ActiveLevel,
+    spawn_grid,
+    utils::{format_date, format_time},
+    ArenaConfig, GameState, GlobalAssets, MapState, SeedConfig,
+};
 
 pub struct MenuPlugin;
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(OnEnter(GameState::Menu), setup_menu)
+            .add_event::<ButtonPressed>()
+            .add_systems(OnEnter(GameState::Menu), (
+                setup_menu,
+                start_demo,
+                spawn_hazards.after(start_demo),
+            ))
             .add_systems(Update, (
-                menu, 
-                simulate_camera_movement,
+                navigate_buttons,
+                highlight_focused_button.after(navigate_buttons),
+            ))
+            .add_systems(Update, (
+                menu.after(navigate_buttons),
+                pick_arena_size.after(navigate_buttons),
+                pick_rivals.after(navigate_buttons),
+                follow_demo_snake,
+                restart_demo,
             ).run_if(in_state(GameState::Menu)))
+            .add_systems(FixedUpdate, steer_demo_snake
+                .before(SnakeStep)
+                .run_if(in_state(GameState::Menu)))
             .add_systems(OnExit(GameState::Menu), cleanup_menu);
     }
 }
-#[derive(Component)]
-struct FakePlayer {
-    pub target_position: Vec3,
-    pub speed: f32,
-}
+
+/// How the snake behind the menu plays, survival keeps its games long
+const DEMO_AI: Difficulty = Difficulty::Survival;
+/// Seconds the demo snake lies dead before its next game
+const DEMO_RESTART_DELAY: f32 = 2.0;
+
+/// Counts down to the next demo game once the bot died
+#[derive(Resource)]
+struct DemoRestart(Timer);
 
 #[derive(Resource)]
 struct MenuData {
     button_entity: Entity,
 }
 
-const NORMAL_BUTTON: Color = Color::srgba(0.15, 0.15, 0.15, 0.4);
+/// Buttons of a panel top to bottom, moved through with the turn actions
+#[derive(Component)]
+pub struct ButtonFocus {
+    buttons: Vec<Entity>,
+    focused: usize,
+}
+
+impl ButtonFocus {
+    pub fn new(buttons: Vec<Entity>) -> Self {
+        Self { buttons, focused: 0 }
+    }
+}
+
+/// A button was clicked or confirmed while focused
+#[derive(Event)]
+pub struct ButtonPressed(pub Entity);
+
+#[derive(Component, Clone, Copy)]
+enum MenuButton {
+    Play,
+    Versus,
+    WatchReplay,
+    Editor,
+    /// Shows the arena size, pressing it steps to the next one
+    ArenaSize,
+    /// Shows how many rivals join a game, pressing it adds one
+    Rivals,
+    /// Shows how the rivals play, pressing it steps to the next way
+    RivalAi,
+}
 
+const NORMAL_BUTTON: Color = Color::srgba(0.15, 0.15, 0.15, 0.4);
 
 
-fn setup_menu(mut commands: Commands, map_state: Res<MapState>) {
-    // setup camera movement
-    let mut rng = thread_rng();
-    let x_rand = rng.gen_range(0..MAP_SIZE);
-    let z_rand = rng.gen_range(0..MAP_SIZE);
-    commands.spawn((
-        Transform::from_xyz(x_rand as f32, 0.0, z_rand as f32),
-        GlobalTransform::default(),
-        CameraFollowTarget,
-        FakePlayer {
-            target_position: Vec3::new(x_rand as f32, 0.0, z_rand as f32),
-            speed: 1.0,
-        },
-    ));
 
+fn setup_menu(
+    mut commands: Commands,
+    map_state: Res<MapState>,
+    last_replay: Res<LastReplay>,
+    high_scores: Res<HighScores>,
+    arena: Res<ArenaConfig>,
+    rivals: Res<RivalConfig>,
+) {
     // setup ui
+    let mut buttons = Vec::new();
     let button_entity = commands
         .spawn((
             Node {
@@ -57,7 +112,7 @@ fn setup_menu(mut commands: Commands, map_state: Res<MapState>) {
                 left: Val::Px(0.),
                 ..default()
             },
-            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7).into()),
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7)),
         ))
         .with_children(|parent| {
             parent.spawn((
@@ -74,7 +129,7 @@ fn setup_menu(mut commands: Commands, map_state: Res<MapState>) {
             ));
             if map_state.score != 0 {
                 parent.spawn((
-                    Text::new(format!("[last game] score {} / time {}", map_state.score, format_time(map_state.time_elapsed) )),
+                    Text::new(format!("[last game] score {} / time {} / seed {}", map_state.score, format_time(map_state.time_elapsed), map_state.seed)),
                     TextFont {
                         font_size: 25.0,
                         ..default()
@@ -83,100 +138,363 @@ fn setup_menu(mut commands: Commands, map_state: Res<MapState>) {
             }
             
             
+            buttons.push(spawn_button(parent, "Play", MenuButton::Play));
+            buttons.push(spawn_button(parent, "Versus", MenuButton::Versus));
+            if last_replay.0.is_some() {
+                buttons.push(spawn_button(parent, "Replay", MenuButton::WatchReplay));
+            }
+            buttons.push(spawn_button(parent, "Editor", MenuButton::Editor));
+            // the options for the next game side by side
             parent
-                .spawn((
-                    Button,
-                    Node {
-                        width: Val::Px(150.),
-                        height: Val::Px(65.),
-                        border: UiRect::all(Val::Px(2.0)),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
-                        ..default()
-                    },
-                    BackgroundColor(NORMAL_BUTTON),
-                    BorderColor(Color::WHITE.with_alpha(0.)),
-                ))
+                .spawn(Node {
+                    column_gap: Val::Px(10.),
+                    ..default()
+                })
                 .with_children(|parent| {
-                    
-                    parent.spawn((
-                        Text::new("Play"),
-                        TextFont {
-                            font_size: 33.0,
-                            ..default()
-                        },
-                        TextColor(Color::srgb(0.9, 0.9, 0.9)),
-                    ));
+                    buttons.push(spawn_button(parent, &arena.size_label(), MenuButton::ArenaSize));
+                    buttons.push(spawn_button(parent, &rivals.count_label(), MenuButton::Rivals));
+                    buttons.push(spawn_button(parent, &rivals.difficulty_label(), MenuButton::RivalAi));
                 });
+            if !high_scores.entries().is_empty() {
+                spawn_high_scores(parent, &high_scores);
+            }
         })
         .id();
+    commands.entity(button_entity).insert(ButtonFocus::new(buttons));
     commands.insert_resource(MenuData { button_entity });
 }
 
-fn menu(
+fn spawn_high_scores(parent: &mut ChildBuilder, high_scores: &HighScores) {
+    parent
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(30.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("High scores"),
+                TextFont {
+                    font_size: 30.0,
+                    ..default()
+                },
+            ));
+            for (rank, entry) in high_scores.entries().iter().enumerate() {
+                parent.spawn((
+                    Text::new(format!(
+                        "{:>2}. {:>3}  {}  {}  {}  seed {}",
+                        rank + 1, entry.score, format_time(entry.time), format_date(entry.date), entry.mode, entry.seed,
+                    )),
+                    TextFont {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                ));
+            }
+        });
+}
+
+pub fn spawn_button(parent: &mut ChildBuilder, label: &str, action: impl Component) -> Entity {
+    parent
+        .spawn((
+            Button,
+            action,
+            Node {
+                width: Val::Px(200.),
+                height: Val::Px(65.),
+                border: UiRect::all(Val::Px(2.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(NORMAL_BUTTON),
+            BorderColor(Color::WHITE.with_alpha(0.)),
+        ))
+        .with_children(|parent| {
+            
+            parent.spawn((
+                Text::new(label),
+                TextFont {
+                    font_size: 33.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.9, 0.9, 0.9)),
+            ));
+        })
+        .id()
+}
+
+/// Focus follows the mouse or the turn actions, a click or `Confirm` presses the focused button
+fn navigate_buttons(
     mut commands: Commands,
     game_assets: Res<GlobalAssets>,
+    actions: Res<ActionState>,
+    mut panels: Query<&mut ButtonFocus>,
+    interaction_query: Query<(Entity, &Interaction), (Changed<Interaction>, With<Button>)>,
+    mut button_pressed: EventWriter<ButtonPressed>,
+) {
+    for mut panel in panels.iter_mut() {
+        let mut pressed = None;
+        let mut focused = panel.focused;
+        for (entity, interaction) in interaction_query.iter() {
+            let Some(index) = panel.buttons.iter().position(|&b| b == entity) else { continue };
+            match *interaction {
+                Interaction::Pressed => pressed = Some(index),
+                Interaction::Hovered => focused = index,
+                Interaction::None => {}
+            }
+        }
+
+        let count = panel.buttons.len();
+        if actions.just_turned(Direction::Down) {
+            focused = (focused + 1) % count;
+        }
+        if actions.just_turned(Direction::Up) {
+            focused = (focused + count - 1) % count;
+        }
+        if actions.just_pressed(Action::Confirm) {
+            pressed = Some(focused);
+        }
+
+        if focused != panel.focused {
+            panel.focused = focused;
+            commands.spawn((
+                AudioPlayer::<AudioSource>(game_assets.pickup.clone()),
+                PlaybackSettings::DESPAWN,
+            ));
+        }
+        if let Some(index) = pressed {
+            commands.spawn((
+                AudioPlayer::<AudioSource>(game_assets.button_click.clone()),
+                PlaybackSettings::DESPAWN,
+            ));
+            button_pressed.send(ButtonPressed(panel.buttons[index]));
+        }
+    }
+}
+
+fn highlight_focused_button(
+    panels: Query<&ButtonFocus, Changed<ButtonFocus>>,
+    mut borders: Query<&mut BorderColor, With<Button>>,
+) {
+    for panel in panels.iter() {
+        for (index, &button) in panel.buttons.iter().enumerate() {
+            if let Ok(mut color) = borders.get_mut(button) {
+                let alpha = if index == panel.focused { 1.0 } else { 0.0 };
+                *color = WHITE.with_alpha(alpha).into();
+            }
+        }
+    }
+}
+
+fn menu(
+    mut commands: Commands,
+    last_replay: Res<LastReplay>,
     mut next_state: ResMut<NextState<GameState>>,
-    mut interaction_query: Query<
-        (&Interaction, &mut BorderColor),
-        (Changed<Interaction>, With<Button>),
-    >,
+    buttons: Query<&MenuButton>,
+    mut button_pressed: EventReader<ButtonPressed>,
 ) {
-    for (interaction, mut color) in &mut interaction_query {
-        match *interaction {
-            Interaction::Pressed => {
-                *color = WHITE.with_alpha(1.0).into();
-                commands.spawn((
-                    AudioPlayer::<AudioSource>(game_assets.button_click.clone()),
-                    PlaybackSettings::DESPAWN,
-                ));
+    for ButtonPressed(entity) in button_pressed.read() {
+        let Ok(action) = buttons.get(*entity) else { continue };
+        match action {
+            MenuButton::Play => {
+                commands.insert_resource(GameMode::Solo);
                 next_state.set(GameState::InGame);
             }
-            Interaction::Hovered => {
-                commands.spawn((
-                    AudioPlayer::<AudioSource>(game_assets.pickup.clone()),
-                    PlaybackSettings::DESPAWN,
-                ));
-                *color = WHITE.with_alpha(1.0).into();
+            MenuButton::Versus => {
+                commands.insert_resource(GameMode::Versus);
+                next_state.set(GameState::InGame);
             }
-            Interaction::None => {
-                *color = WHITE.with_alpha(0.0).into();
+            MenuButton::Editor => next_state.set(GameState::Editor),
+            MenuButton::ArenaSize | MenuButton::Rivals | MenuButton::RivalAi => {}
+            MenuButton::WatchReplay => {
+                if let Some(replay) = last_replay.0.clone() {
+                    commands.insert_resource(Playback(replay));
+                    next_state.set(GameState::Replay);
+                }
             }
         }
     }
 }
 
 fn cleanup_menu(
-    mut commands: Commands, 
-    menu_data: Res<MenuData>, 
-    food_query: Query<Entity, With<Food>>,
-    fake_player: Query<Entity, With<FakePlayer>>,
+    mut commands:   Commands,
+    game_assets:    Res<GlobalAssets>,
+    level:          Res<ActiveLevel>,
+    menu_data:      Res<MenuData>,
+    mut map_state:  ResMut<MapState>,
+    mut heads:      Query<(Entity, &mut Snake, &mut Transform), Without<SnakeBody>>,
+    leftovers:      Query<Entity, Or<(With<SnakeBody>, With<Food>)>>,
+    hazards:        Query<Entity, With<Hazard>>,
 ) {
     commands.entity(menu_data.button_entity).despawn_recursive();
-    for entity in food_query.iter() {
+    // games and the editor start from the world `spawn_world` built
+    clear_demo_world(&mut commands, &game_assets, &level.0, &mut map_state, &mut heads, leftovers.iter());
+    for (head, ..) in heads.iter() {
+        commands.entity(head).remove::<CameraFollowTarget>();
+    }
+    for entity in hazards.iter() {
         commands.entity(entity).despawn_recursive();
     }
-    commands.entity(fake_player.single()).despawn_recursive();
+    commands.remove_resource::<DemoRestart>();
 }
 
+/// A game for the bot behind the menu, on the world `spawn_world` built
+fn start_demo(
+    mut commands:   Commands,
+    level:          Res<ActiveLevel>,
+    mut sim:        ResMut<SnakeSim>,
+    mut rng:        ResMut<GameRng>,
+    mut spawn_food_event: EventWriter<SpawnFoodEvent>,
+) {
+    new_demo_game(&level.0, &mut sim, &mut rng, &mut spawn_food_event);
+    commands.insert_resource(DemoRestart(Timer::from_seconds(DEMO_RESTART_DELAY, TimerMode::Once)));
+}
 
-fn simulate_camera_movement(
-    time: Res<Time>,
-    mut fake_player: Query<(&mut Transform, &mut FakePlayer)>,
+fn follow_demo_snake(
+    mut commands:   Commands,
+    heads:          Query<Entity, (With<Snake>, Without<CameraFollowTarget>)>,
 ) {
-    let (mut player_transform, mut player) = match fake_player.get_single_mut() {
-        Ok(player) => player,
-        Err(_) => return,
-    };
+    for head in heads.iter() {
+        commands.entity(head).insert(CameraFollowTarget::default());
+    }
+}
 
-    if (player_transform.translation - player.target_position).length() < 0.1 {
-        let mut rng = thread_rng();
-        let x_rand = rng.gen_range(0..MAP_SIZE);
-        let z_rand = rng.gen_range(0..MAP_SIZE);
-        player.target_position = Vec3::new(x_rand as f32, 0.0, z_rand as f32);
-    } else {
-        let player_position = player_transform.translation;
-        player_transform.translation += (player.target_position - player_position).normalize() * player.speed * time.delta_secs();
+/// Pick the bot's heading right before the grid step
+fn steer_demo_snake(mut sim: ResMut<SnakeSim>) {
+    if sim.is_alive() {
+        let direction = choose_direction(&sim, 0, DEMO_AI);
+        sim.turn_snake(0, direction);
     }
 }
 
+/// The bot died, play another game after a moment
+fn restart_demo(
+    mut commands:   Commands,
+    time:           Res<Time>,
+    game_assets:    Res<GlobalAssets>,
+    level:          Res<ActiveLevel>,
+    mut restart:    ResMut<DemoRestart>,
+    mut map_state:  ResMut<MapState>,
+    mut sim:        ResMut<SnakeSim>,
+    mut rng:        ResMut<GameRng>,
+    mut heads:      Query<(Entity, &mut Snake, &mut Transform), Without<SnakeBody>>,
+    leftovers:      Query<Entity, Or<(With<SnakeBody>, With<Food>)>>,
+    mut spawn_food_event: EventWriter<SpawnFoodEvent>,
+) {
+    if sim.is_alive() || !restart.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    restart.0.reset();
+    clear_demo_world(&mut commands, &game_assets, &level.0, &mut map_state, &mut heads, leftovers.iter());
+    new_demo_game(&level.0, &mut sim, &mut rng, &mut spawn_food_event);
+}
+
+/// The arena size button was pressed: switch to the next size, which replaces
+/// a level file or an edited level, and play the demo on it
+fn pick_arena_size(
+    mut commands:   Commands,
+    game_assets:    Res<GlobalAssets>,
+    seed_config:    Res<SeedConfig>,
+    mut arena:      ResMut<ArenaConfig>,
+    mut level:      ResMut<ActiveLevel>,
+    mut map_state:  ResMut<MapState>,
+    mut sim:        ResMut<SnakeSim>,
+    mut rng:        ResMut<GameRng>,
+    mut heads:      Query<(Entity, &mut Snake, &mut Transform), Without<SnakeBody>>,
+    leftovers:      Query<Entity, Or<(With<SnakeBody>, With<Food>, With<Hazard>)>>,
+    mut cameras:    Query<&mut TopdownCamera>,
+    buttons:        Query<(&MenuButton, &Children)>,
+    mut texts:      Query<&mut Text>,
+    mut button_pressed: EventReader<ButtonPressed>,
+    mut spawn_food_event: EventWriter<SpawnFoodEvent>,
+) {
+    let mut picked = false;
+    for ButtonPressed(entity) in button_pressed.read() {
+        let Ok((MenuButton::ArenaSize, children)) = buttons.get(*entity) else { continue };
+        arena.next_size();
+        for &child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.0 = arena.size_label();
+            }
+        }
+        picked = true;
+    }
+    if !picked {
+        return;
+    }
+
+    // a fixed seed also fixes the generated layout
+    level.0 = arena.level(&mut GameRng::new(seed_config.0.unwrap_or_else(rand::random)));
+    for mut camera in cameras.iter_mut() {
+        camera.offset = level.camera_offset();
+    }
+    clear_demo_world(&mut commands, &game_assets, &level.0, &mut map_state, &mut heads, leftovers.iter());
+    new_demo_game(&level.0, &mut sim, &mut rng, &mut spawn_food_event);
+}
+
+/// A rival button was pressed: step to the next count or way of playing
+fn pick_rivals(
+    mut rivals:     ResMut<RivalConfig>,
+    buttons:        Query<(&MenuButton, &Children)>,
+    mut texts:      Query<&mut Text>,
+    mut button_pressed: EventReader<ButtonPressed>,
+) {
+    for ButtonPressed(entity) in button_pressed.read() {
+        let Ok((action, children)) = buttons.get(*entity) else { continue };
+        let label = match action {
+            MenuButton::Rivals => {
+                rivals.next_count();
+                rivals.count_label()
+            }
+            MenuButton::RivalAi => {
+                rivals.next_difficulty();
+                rivals.difficulty_label()
+            }
+            _ => continue,
+        };
+        for &child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.0 = label.clone();
+            }
+        }
+    }
+}
+
+fn new_demo_game(
+    level:          &Level,
+    sim:            &mut SnakeSim,
+    rng:            &mut GameRng,
+    spawn_food_event: &mut EventWriter<SpawnFoodEvent>,
+) {
+    (*sim, *rng) = start_game(level, rand::random(), 1, 0);
+    for food in sim.foods() {
+        spawn_food_event.send(SpawnFoodEvent(food.pos, food.kind));
+    }
+}
+
+/// Put the world back the way `spawn_world` built it: floor cubes down, the
+/// head on the start, no body and no food
+fn clear_demo_world(
+    commands:       &mut Commands,
+    game_assets:    &GlobalAssets,
+    level:          &Level,
+    map_state:      &mut MapState,
+    heads:          &mut Query<(Entity, &mut Snake, &mut Transform), Without<SnakeBody>>,
+    leftovers:      impl Iterator<Item = Entity>,
+) {
+    for &cube in map_state.grid.iter().flatten() {
+        commands.entity(cube).despawn_recursive();
+    }
+    let grid = spawn_grid(commands, game_assets, level);
+    map_state.set_grid(grid, level);
+    for (_, mut snake, mut transform) in heads.iter_mut() {
+        *snake = Snake::new(level.start);
+        *transform = Transform::from_translation(level.cell_world(level.start));
+    }
+    for entity in leftovers {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,109 @@
+use bevy::prelude::*;
+
+use crate::{
+    input::{Action, ActionState},
+    menu::{spawn_button, ButtonFocus, ButtonPressed},
+    GameState, PauseState, Playing,
+};
+
+pub struct PausePlugin;
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(OnEnter(PauseState::Paused), (freeze_time, spawn_pause_menu))
+            .add_systems(OnExit(PauseState::Paused), (unfreeze_time, cleanup_pause_menu))
+            .add_systems(Update, toggle_pause.run_if(in_state(Playing)))
+            .add_systems(Update, pause_menu.run_if(in_state(PauseState::Paused)));
+    }
+}
+
+#[derive(Component)]
+struct PauseMenu;
+
+#[derive(Component, Clone, Copy)]
+enum PauseButton {
+    Resume,
+    Restart,
+    Quit,
+}
+
+fn toggle_pause(
+    actions:        Res<ActionState>,
+    state:          Res<State<PauseState>>,
+    mut next_state: ResMut<NextState<PauseState>>,
+) {
+    let back = *state.get() == PauseState::Paused && actions.just_pressed(Action::Back);
+    if actions.just_pressed(Action::Pause) || back {
+        next_state.set(match state.get() {
+            PauseState::Running => PauseState::Paused,
+            PauseState::Paused => PauseState::Running,
+        });
+    }
+}
+
+/// Stops the fixed grid step, animations and timers, they all run on virtual time
+fn freeze_time(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+fn unfreeze_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
+
+fn spawn_pause_menu(mut commands: Commands) {
+    let mut buttons = Vec::new();
+    let root = commands
+        .spawn((
+            PauseMenu,
+            Node {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(10.),
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.5)),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Paused"),
+                TextFont {
+                    font_size: 50.0,
+                    ..default()
+                },
+            ));
+            buttons.push(spawn_button(parent, "Resume", PauseButton::Resume));
+            buttons.push(spawn_button(parent, "Restart", PauseButton::Restart));
+            buttons.push(spawn_button(parent, "Quit", PauseButton::Quit));
+        })
+        .id();
+    commands.entity(root).insert(ButtonFocus::new(buttons));
+}
+
+fn pause_menu(
+    mut pause_state:    ResMut<NextState<PauseState>>,
+    mut game_state:     ResMut<NextState<GameState>>,
+    buttons:            Query<&PauseButton>,
+    mut button_pressed: EventReader<ButtonPressed>,
+) {
+    for ButtonPressed(entity) in button_pressed.read() {
+        let Ok(action) = buttons.get(*entity) else { continue };
+        match action {
+            PauseButton::Resume => pause_state.set(PauseState::Running),
+            PauseButton::Restart => game_state.set(GameState::Restarting),
+            PauseButton::Quit => game_state.set(GameState::Menu),
+        }
+    }
+}
+
+fn cleanup_pause_menu(
+    mut commands:   Commands,
+    menu:           Query<Entity, With<PauseMenu>>,
+) {
+    for entity in menu.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
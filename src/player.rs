@@ -1,213 +1,358 @@
-use std::time::Duration;
+use std::{collections::VecDeque, time::Duration};
 
 use bevy::prelude::*;
 
-use crate::{camera::CameraFollowTarget, game_flow::{Food, SpawnFoodEvent, SpawnSnakeTail}, GameState, GlobalAssets};
+use snake_3d::{food::FoodKind, grid::GridPos, level::Level, rng::GameRng, sim::{SimEvent, SnakeSim}};
 
-pub const BASE_SPEED: f32 = 3.0;
-pub const SPEEED_INCREASE_AFTER_NUM_TAILS: usize = 10;
+use crate::{animation::TailAppearAnimation, camera::{CameraFollowTarget, CameraFrame, CameraJump}, input::{Action, ActionState}, game_flow::{start_run, Food, MapModifyEvent, SpawnFoodEvent, SpawnSnakeTail}, GameState, GlobalAssets, PauseState, Playing, Simulating};
+pub use snake_3d::sim::Direction;
 
 pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app
-        .add_systems(OnEnter(GameState::InGame), setup_camera_follow)
+        .add_systems(OnEnter(Playing), (setup_camera_follow, update_step_rate.after(start_run)))
+        .add_systems(FixedUpdate, (
+            apply_queued_turn.before(SnakeStep),
+            step_snake.in_set(SnakeStep),
+        ).run_if(in_state(Simulating)))
         .add_systems(Update, (
-            move_snake,
-            handle_direction_change,
-        ).run_if(in_state(GameState::InGame)));
+            update_step_rate,
+            interpolate_snake,
+        ).run_if(in_state(Simulating)))
+        .add_systems(Update, handle_direction_change
+            .run_if(in_state(GameState::InGame).and(in_state(PauseState::Running))));
     }
 }
 
+/// The grid step, systems feeding it input run before this set
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SnakeStep;
 
+/// Turns buffered ahead of the grid step, extra key presses are dropped
+pub const MAX_QUEUED_TURNS: usize = 3;
+
+
+/// Mirror of the snake head, the logical state lives in `SnakeSim`.
+/// Rendering interpolates from `prev_cell` to `cell` between grid steps.
 #[derive(Component, Clone)]
 pub struct Snake {
-    pub pos: Vec3,
-    pub direction: Direction,
-    pub target_position: Vec3,
-    pub speed: f32,
-    pub wait: Timer,
+    /// Which of the simulation's snakes it mirrors
+    pub index: usize,
+    pub prev_cell: GridPos,
+    pub cell: GridPos,
     pub bodies: Vec<Entity>,
+    /// Pressed turns not applied yet, as pressed on screen. They become
+    /// headings when applied, with the view of that step
+    pub turns: VecDeque<Direction>,
+    /// Heading that points up on screen, it turns with the snake over the edges of a cube
+    pub view_up: Direction,
+    /// Grid step that took the head over the edge of a wrapping arena, the
+    /// camera jumps along once the head shows on the far side
+    pub seam_jump: Option<u64>,
 }
 
-
 impl Snake {
-    fn get_next_target(&self) -> Vec3 {
-        self.pos + self.direction.norm()
-    }
-}
-
-impl Default for Snake {
-    fn default() -> Self {
-        let start_pos = Vec3::new(12.0, 0.0, 12.0);
-        let start_dir = Direction::Up;
-        let start_target_pos = start_pos + start_dir.norm();
+    pub fn new(cell: GridPos) -> Self {
         Self {
-            direction: start_dir,
-            pos: start_pos,
-            speed: BASE_SPEED,
-            target_position: start_target_pos,
-            wait: Timer::from_seconds(1.0, TimerMode::Repeating),
+            index: 0,
+            prev_cell: cell,
+            cell,
             bodies: Vec::new(),
+            turns: VecDeque::new(),
+            view_up: Direction::Up,
+            seam_jump: None,
+        }
+    }
+
+    /// Heading of a turn pressed on screen
+    pub fn screen_turn(&self, direction: Direction) -> Direction {
+        rotate(direction, quarter_turns(Direction::Up, self.view_up))
+    }
+
+    /// On screen direction of a heading, the inverse of `screen_turn`
+    pub fn screen_direction(&self, heading: Direction) -> Direction {
+        rotate(heading, 4 - quarter_turns(Direction::Up, self.view_up))
+    }
+
+    /// Buffer a turn pressed on screen, checked against the one it will follow.
+    /// Reversals and turns past `MAX_QUEUED_TURNS` are refused, repeats are ignored.
+    pub fn queue_turn(&mut self, current: Direction, direction: Direction) -> bool {
+        let last = self.turns.back().copied().unwrap_or_else(|| self.screen_direction(current));
+        if direction == last {
+            return true;
         }
+        if self.turns.len() >= MAX_QUEUED_TURNS || direction == last.opposite() {
+            return false;
+        }
+        self.turns.push_back(direction);
+        true
     }
 }
 
 #[derive(Component, Clone)]
 pub struct SnakeBody {
-    pub target_position: Vec3,
+    pub prev_cell: GridPos,
+    pub cell: GridPos,
 }
 
 impl SnakeBody {
-    pub fn new(at: Vec3) -> Self {
-        Self { target_position: at }
-    }
-
-}
-
-#[derive(Clone, Copy, PartialEq)]
-pub enum Direction { Up, Down, Left, Right }
-impl Direction {
-    pub fn norm(&self) -> Vec3 {
-        match self {
-            Direction::Up => Vec3::new(0.0 ,0.0, -1.0),
-            Direction::Down => Vec3::new(0.0, 0.0 ,1.0),
-            Direction::Left => Vec3::new(-1.0, 0.0, 0.0),
-            Direction::Right => Vec3::new(1.0, 0.0, 0.0),
-        }
+    pub fn new(at: GridPos) -> Self {
+        Self { prev_cell: at, cell: at }
     }
 
 }
 
 fn setup_camera_follow(
     mut commands: Commands,
-    player: Query<Entity, With<Snake>>,
+    players: Query<(Entity, &Snake)>,
 ) {
-    if let Ok(player) = player.get_single() {
-        commands.entity(player).insert(CameraFollowTarget);
+    for (player, snake) in players.iter() {
+        commands.entity(player).insert(CameraFollowTarget(snake.index));
     }
 }
 
 
-fn move_snake(
+/// One grid step per `FixedUpdate` run, see `update_step_rate`
+fn step_snake(
     mut commands: Commands,
-    time: Res<Time>,
     game_assets: Res<GlobalAssets>,
-    mut snake_query: Query<(&mut Transform, &mut Snake), (With<Snake>, Without<Food>, Without<SnakeBody>)>,
-    mut snake_bodies_query: Query<(&mut Transform, &mut SnakeBody),  (With<SnakeBody>, Without<Snake>, Without<Food>)>,
-    food_query: Query<(Entity, &mut Transform), (With<Food>, Without<Snake>, Without<SnakeBody>)>,
+    mut sim: ResMut<SnakeSim>,
+    mut rng: ResMut<GameRng>,
+    mut snake_query: Query<&mut Snake, Without<SnakeBody>>,
+    mut snake_bodies_query: Query<&mut SnakeBody, Without<Snake>>,
+    food_query: Query<(Entity, &Food)>,
     mut spawn_food_event_writer: EventWriter<SpawnFoodEvent>,
     mut spawn_snake_tail_event_writer: EventWriter<SpawnSnakeTail>,
-    mut next_state: ResMut<NextState<GameState>>,
+    mut map_modify_event_writer: EventWriter<MapModifyEvent>,
+    playing: Option<Res<State<Playing>>>,
 ) {
+    // the menu's demo plays silently
+    let audible = playing.is_some();
+    let events = sim.tick(rng.as_mut());
+    for mut snake in snake_query.iter_mut() {
+        let Some(state) = sim.snakes().get(snake.index) else { continue };
+        snake.prev_cell = snake.cell;
+        snake.cell = state.head();
+        // going over an edge of a cube turns the heading, the view turns with
+        // it. A belt turns the heading on the same face and leaves the view be
+        let level = sim.level();
+        if level.cell_normal(snake.prev_cell) != level.cell_normal(snake.cell) {
+            let heading = state.direction();
+            let moved = Direction::ALL.into_iter()
+                .find(|&direction| level.step(snake.prev_cell, direction) == Some((snake.cell, heading)));
+            if let Some(moved) = moved {
+                snake.view_up = rotate(snake.view_up, quarter_turns(moved, heading));
+            }
+        }
 
-    let (mut transform, mut snake) = match snake_query.get_single_mut() {
-        Ok(transform) => transform,
-        Err(_) => return,
-    };
-    let snake_speed = snake.speed;
-    snake.wait.tick(Duration::from_secs_f32(time.delta_secs() * snake_speed));
-
-
-    if snake.wait.just_finished() {
-        snake.pos = snake.target_position;
-        transform.translation = snake.target_position;
-        snake.target_position = snake.get_next_target();
-
-        // update snake bodies
-        let snake_pos = snake.pos;
-        // let snake_target = snake.target_position;
-        let mut prev_body_pos: Vec3 = Vec3::ZERO;
-        for (body_index, entity) in snake.bodies.iter_mut().enumerate() {
-            if let Ok((mut body_transform, mut body_data)) = snake_bodies_query.get_mut(*entity) {
-                body_transform.translation = body_data.target_position;
-                body_data.target_position = if body_index == 0 {
-                    snake_pos
-                } else {
-                    prev_body_pos
-                };
-                prev_body_pos = body_transform.translation;
-
-                // // self collision check :D
-                // if snake_target == body_data.target_position {
-                //     next_state.set(GameState::GameOver);
-                // }
+        // update snake bodies, each one moves onto the segment in front of it
+        for (body_index, entity) in snake.bodies.iter().enumerate() {
+            if let Ok(mut body_data) = snake_bodies_query.get_mut(*entity) {
+                body_data.prev_cell = body_data.cell;
+                if let Some(&segment) = state.segments().get(body_index + 1) {
+                    body_data.cell = segment;
+                }
             }
         }
-        
+    }
 
-        // check for food collision
-        if let Ok((entity, food_transform)) = food_query.get_single() {
-            if (snake.pos.xz() - food_transform.translation.xz()).length() < 0.1 {
+    for event in events {
+        match event {
+            SimEvent::AteFood { snake, pos: cell, kind } => {
                 // play audio
-                commands.spawn((
-                    AudioPlayer::<AudioSource>(game_assets.pickup.clone()),
-                    PlaybackSettings::DESPAWN,
-                ));
+                if audible {
+                    commands.spawn((
+                        AudioPlayer::<AudioSource>(game_assets.pickup.clone()),
+                        PlaybackSettings::DESPAWN,
+                    ));
+                }
                 // despawn food
-                commands.entity(entity).despawn_recursive();
-                // spawn new food
-                spawn_food_event_writer.send(SpawnFoodEvent);
-                spawn_snake_tail_event_writer.send(SpawnSnakeTail);
+                for (entity, food) in food_query.iter() {
+                    if food.cell == cell {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+                if kind != FoodKind::Shrink {
+                    spawn_snake_tail_event_writer.send(SpawnSnakeTail(snake));
+                }
+            }
+            SimEvent::FoodSpawned(cell, kind) => {
+                spawn_food_event_writer.send(SpawnFoodEvent(cell, kind));
+            }
+            SimEvent::FoodExpired(cell) => {
+                for (entity, food) in food_query.iter() {
+                    if food.cell == cell {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+            }
+            SimEvent::Shrunk { snake, count } => {
+                let Some(mut snake) = snake_query.iter_mut().find(|s| s.index == snake) else { continue };
+                let keep = snake.bodies.len().saturating_sub(count);
+                for entity in snake.bodies.drain(keep..) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            SimEvent::EffectStarted { .. } | SimEvent::SpeedBoost(_) => {
+                if audible {
+                    commands.spawn((
+                        AudioPlayer::<AudioSource>(game_assets.speed_boost.clone()),
+                        PlaybackSettings::DESPAWN,
+                    ));
+                }
             }
+            SimEvent::ObstaclesRaised(cells) => {
+                map_modify_event_writer.send(MapModifyEvent::Raise(cells));
+            }
+            SimEvent::ObstaclesLowered(cells) => {
+                map_modify_event_writer.send(MapModifyEvent::Lower(cells));
+            }
+            SimEvent::ObstaclesCleared(cells) => {
+                map_modify_event_writer.send(MapModifyEvent::Clear(cells));
+            }
+            SimEvent::Wrapped { snake } => {
+                let Some(mut snake) = snake_query.iter_mut().find(|s| s.index == snake) else { continue };
+                snake.seam_jump = Some(sim.ticks());
+            }
+            SimEvent::Moved { .. } | SimEvent::EffectEnded { .. } | SimEvent::Died { .. } => {}
         }
-        
-    } else {
-        // update snake head pos
-        transform.translation += (snake.target_position - snake.pos).normalize() * time.delta_secs() * snake.speed;
-        // update snake bodies pos
-        let snake_speed = snake.speed;
-        for entity in snake.bodies.iter_mut() {
-            if let Ok((mut body_transform, body_data)) = snake_bodies_query.get_mut(*entity) {
-                let curr_pos = body_transform.translation;
-                body_transform.translation += (body_data.target_position - curr_pos).normalize_or_zero() * time.delta_secs() * snake_speed;
+    }
+}
+
+/// Take the next buffered turn right before the grid step, turned into a
+/// heading with the current view. Turns that no longer change the heading or
+/// would reverse it are skipped for the next one
+pub fn apply_queued_turn(
+    mut sim:            ResMut<SnakeSim>,
+    mut snake_query:    Query<&mut Snake, Without<SnakeBody>>,
+) {
+    for mut snake in snake_query.iter_mut() {
+        let current = sim.snake(snake.index).direction();
+        while let Some(direction) = snake.turns.pop_front() {
+            let direction = snake.screen_turn(direction);
+            if direction != current && sim.turn_snake(snake.index, direction) {
+                break;
             }
         }
     }
 }
 
+/// Run `FixedUpdate` once per grid step at the snake's current speed
+fn update_step_rate(
+    sim: Res<SnakeSim>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+) {
+    let timestep = Duration::from_secs_f32(1.0 / sim.speed());
+    if fixed_time.timestep() != timestep {
+        fixed_time.set_timestep(timestep);
+    }
+}
+
+/// Place head and bodies between their previous and current cells in world space
+fn interpolate_snake(
+    fixed_time: Res<Time<Fixed>>,
+    sim: Res<SnakeSim>,
+    mut snake_query: Query<(&mut Transform, &mut CameraFrame, &mut Snake), Without<SnakeBody>>,
+    mut snake_bodies_query: Query<(&mut Transform, &SnakeBody, Has<TailAppearAnimation>), Without<Snake>>,
+    mut camera_jumps: EventWriter<CameraJump>,
+) {
+    let alpha = fixed_time.overstep_fraction();
+    let level = sim.level();
+    for (mut transform, mut frame, mut snake) in snake_query.iter_mut() {
+        let (translation, scale) = step_transform(level, snake.prev_cell, snake.cell, alpha);
+        // the head came out of the opposite edge, halfway through the step or
+        // with a later step already taken
+        if snake.seam_jump.is_some_and(|tick| alpha >= 0.5 || sim.ticks() > tick) {
+            snake.seam_jump = None;
+            camera_jumps.send(CameraJump { view: snake.index, offset: translation - transform.translation });
+        }
+        transform.translation = translation;
+        transform.scale = Vec3::splat(scale);
+
+        let normal = level.cell_normal(snake.cell);
+        let down = level.direction_world(snake.cell, snake.view_up.opposite());
+        transform.rotation = Quat::from_rotation_arc(Vec3::Y, normal);
+        *frame = CameraFrame::new(normal, down);
+    }
+    for (mut transform, body, appearing) in snake_bodies_query.iter_mut() {
+        let (translation, scale) = step_transform(level, body.prev_cell, body.cell, alpha);
+        transform.translation = translation;
+        // a new tail grows in on its own
+        if !appearing {
+            transform.scale = Vec3::splat(scale);
+        }
+    }
+}
+
+/// Position and scale of a segment `alpha` of the way through a step. A step
+/// over an edge of a cube turns around the edge, one across the seam of a
+/// wrapping arena or through a portal shrinks into the entrance and grows back
+/// out of the exit.
+fn step_transform(level: &Level, prev: GridPos, cell: GridPos, alpha: f32) -> (Vec3, f32) {
+    let (from, to) = (level.cell_world(prev), level.cell_world(cell));
+    // stepping onto a portal always comes out of the other end
+    if let Some(entrance) = level.portal_exit(cell).filter(|_| prev != cell) {
+        let step = level.cell_world(entrance) - from;
+        return if alpha < 0.5 {
+            (from.lerp(from + step, alpha), 1.0 - 2.0 * alpha)
+        } else {
+            ((to - step).lerp(to, alpha), 2.0 * alpha - 1.0)
+        };
+    }
+    if level.cube_net().is_some() {
+        let normal = level.cell_normal(cell);
+        if level.cell_normal(prev) == normal {
+            return (from.lerp(to, alpha), 1.0);
+        }
+        let corner = from + normal * (to - from).dot(normal);
+        return if alpha < 0.5 {
+            (from.lerp(corner, 2.0 * alpha), 1.0)
+        } else {
+            (corner.lerp(to, 2.0 * alpha - 1.0), 1.0)
+        };
+    }
+    // neighbours and ramps between layers are at most one cell apart sideways
+    let delta = (to - from).with_y(0.0);
+    if delta.x.abs() <= 1.5 && delta.z.abs() <= 1.5 {
+        return (from.lerp(to, alpha), 1.0);
+    }
+    let across = |d: f32| if d.abs() > 1.5 { -d.signum() } else { d };
+    let step = Vec3::new(across(delta.x), 0.0, across(delta.z));
+    if alpha < 0.5 {
+        (from.lerp(from + step, alpha), 1.0 - 2.0 * alpha)
+    } else {
+        ((to - step).lerp(to, alpha), 2.0 * alpha - 1.0)
+    }
+}
+
 fn handle_direction_change(
     mut commands:   Commands,
-    keyboard:       Res<ButtonInput<KeyCode>>,
+    actions:        Res<ActionState>,
     game_assets:    Res<GlobalAssets>,
-    mut query:      Query<&mut Snake>,
+    sim:            Res<SnakeSim>,
+    mut snake_query: Query<&mut Snake, Without<SnakeBody>>,
 ) {
-    for mut snake in query.iter_mut() {
-        let mut play_fail_audio = || {
-            commands.spawn((
-                AudioPlayer::<AudioSource>(game_assets.pickup.clone()),
-                PlaybackSettings::DESPAWN,
-            ));
-        };
-        if keyboard.just_pressed(KeyCode::KeyA) {
-            if snake.direction == Direction::Right {
-                play_fail_audio();
-            }
-            else {
-                snake.direction = Direction::Left;
-            }
-        } else if keyboard.just_pressed(KeyCode::KeyD) {
-            if snake.direction == Direction::Right {
-                play_fail_audio();
-            }
-            else {
-                snake.direction = Direction::Right;
-            }
-        } else if keyboard.just_pressed(KeyCode::KeyW) {
-            if snake.direction == Direction::Down {
-                play_fail_audio();
-            }
-            else {
-                snake.direction = Direction::Up;
-            }
-        } else if keyboard.just_pressed(KeyCode::KeyS) {
-            if snake.direction == Direction::Up {
-                play_fail_audio();
-            }
-            else {
-                snake.direction = Direction::Down;
+    let players = sim.players();
+    for mut snake in snake_query.iter_mut().filter(|snake| snake.index < players) {
+        let current = sim.snake(snake.index).direction();
+        let pressed = Action::turns_of(snake.index, players).iter().filter(|&&action| actions.just_pressed(action));
+        for direction in pressed.filter_map(Action::direction) {
+            if !snake.queue_turn(current, direction) {
+                commands.spawn((
+                    AudioPlayer::<AudioSource>(game_assets.pickup.clone()),
+                    PlaybackSettings::DESPAWN,
+                ));
             }
-        } 
-        
+        }
     }
 }
+
+/// Quarter turns clockwise from one heading to another
+fn quarter_turns(from: Direction, to: Direction) -> usize {
+    (0..4).find(|&turns| rotate(from, turns) == to).unwrap_or(0)
+}
+
+fn rotate(direction: Direction, quarter_turns: usize) -> Direction {
+    (0..quarter_turns).fold(direction, |direction, _| direction.clockwise())
+}
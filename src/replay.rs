@@ -0,0 +1,277 @@
+//! Recorded games: the seed, the level and every turn, enough to replay a run exactly.
+
+use std::{fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{level::Level, rng::GameRng, sim::{Direction, SnakeSim}};
+
+/// Format of saved replays, files of any other version are refused
+pub const REPLAY_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    #[serde(alias = "config")]
+    pub level: Level,
+    /// Heading changes in order, each tagged with the number of steps taken
+    /// before it was in effect
+    pub turns: Vec<(u64, Direction)>,
+    /// Snakes in the game, the first one's turns are in `turns`
+    #[serde(default = "one")]
+    pub snakes: usize,
+    /// How many of `snakes` are computer rivals, they come last
+    #[serde(default)]
+    pub rivals: usize,
+    /// Heading changes of the other snakes, with the snake's index. Rivals
+    /// are recorded like players, so they play back without their AI.
+    #[serde(default)]
+    pub snake_turns: Vec<(u64, usize, Direction)>,
+    pub final_score: i32,
+    pub final_tick: u64,
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    Version(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "replay io error: {err}"),
+            ReplayError::Parse(err) => write!(f, "invalid replay: {err}"),
+            ReplayError::Serialize(err) => write!(f, "could not write replay: {err}"),
+            ReplayError::Version(v) => write!(f, "unsupported replay version {v}, expected {REPLAY_VERSION}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+/// Set up a game the same way for live play, playback and headless runs
+pub fn start_game(level: &Level, seed: u64, players: usize, rivals: usize) -> (SnakeSim, GameRng) {
+    let mut rng = GameRng::new(seed);
+    let mut sim = SnakeSim::with_snakes(level, players, rivals);
+    for &food in level.foods.iter() {
+        sim.place_food(food);
+    }
+    sim.refill_food(&mut rng, &mut Vec::new());
+    (sim, rng)
+}
+
+impl Replay {
+    pub fn new(seed: u64, level: Level, snakes: usize, rivals: usize) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            seed,
+            level,
+            turns: Vec::new(),
+            snakes,
+            rivals,
+            snake_turns: Vec::new(),
+            final_score: 0,
+            final_tick: 0,
+        }
+    }
+
+    /// Record a snake's heading for the next step if input changed it from
+    /// `after_step`, the heading the last step left it with. Belts and the
+    /// edges of a cube turn snakes too, comparing against the last recorded
+    /// heading would miss a turn back.
+    pub fn record_direction(&mut self, tick: u64, snake: usize, after_step: Direction, direction: Direction) {
+        if direction == after_step {
+            return;
+        }
+        if snake == 0 {
+            self.turns.push((tick, direction));
+        } else {
+            self.snake_turns.push((tick, snake, direction));
+        }
+    }
+
+    pub fn finish(&mut self, sim: &SnakeSim) {
+        self.final_score = sim.score();
+        self.final_tick = sim.ticks();
+    }
+
+    /// Apply the heading recorded for the step after `tick` steps
+    pub fn apply_turns(&self, tick: u64, sim: &mut SnakeSim) {
+        for &(_, direction) in self.turns.iter().filter(|(t, _)| *t == tick) {
+            sim.set_direction(direction);
+        }
+        for &(_, snake, direction) in self.snake_turns.iter().filter(|(t, ..)| *t == tick) {
+            sim.set_snake_direction(snake, direction);
+        }
+    }
+
+    /// Play the whole replay without rendering and return the final state
+    pub fn run(&self) -> SnakeSim {
+        let (mut sim, mut rng) = start_game(&self.level, self.seed, self.snakes.saturating_sub(self.rivals), self.rivals);
+        while sim.is_alive() && sim.ticks() < self.final_tick {
+            self.apply_turns(sim.ticks(), &mut sim);
+            sim.tick(&mut rng);
+        }
+        sim
+    }
+
+    /// Whether replaying reproduces the recorded result
+    pub fn verify(&self) -> bool {
+        let sim = self.run();
+        sim.score() == self.final_score && sim.ticks() == self.final_tick
+    }
+
+    pub fn to_ron(&self) -> Result<String, ReplayError> {
+        ron::to_string(self).map_err(ReplayError::Serialize)
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, ReplayError> {
+        let replay: Replay = ron::from_str(text).map_err(ReplayError::Parse)?;
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::Version(replay.version));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Self::from_ron(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ai::{choose_direction, Difficulty},
+        grid::GridPos,
+        level::{Conveyor, ObstacleSchedule},
+    };
+
+    /// Play `ticks` steps on `level`, turning right every few steps or when
+    /// the way ahead is closed, and record them
+    fn record(level: Level, seed: u64, ticks: u64) -> Replay {
+        let mut replay = Replay::new(seed, level, 1, 0);
+        let (mut sim, mut rng) = start_game(&replay.level, seed, 1, 0);
+        while sim.is_alive() && sim.ticks() < ticks {
+            let after_step = sim.direction();
+            let mut turns = [after_step, after_step.clockwise(), after_step.clockwise().opposite()];
+            if sim.ticks() % 4 == 3 {
+                turns.swap(0, 1);
+            }
+            let open = |direction: &Direction| sim.step(sim.head(), *direction)
+                .is_some_and(|(to, _)| !sim.is_blocked(to) && !sim.segments().contains(&to));
+            if let Some(&direction) = turns.iter().find(|direction| open(direction)) {
+                sim.turn(direction);
+            }
+            replay.record_direction(sim.ticks(), 0, after_step, sim.direction());
+            sim.tick(&mut rng);
+        }
+        replay.finish(&sim);
+        replay
+    }
+
+    #[test]
+    fn saved_replays_load_and_play_back() {
+        let replay = record(Level::open(12, 12), 7, 200);
+        assert!(replay.final_tick > 50 && !replay.turns.is_empty());
+        assert!(replay.verify());
+
+        let path = std::env::temp_dir().join(format!("snake_3d_replay_{}.ron", std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, replay);
+        assert!(loaded.verify());
+
+        let sim = loaded.run();
+        assert_eq!((sim.score(), sim.ticks()), (replay.final_score, replay.final_tick));
+    }
+
+    #[test]
+    fn turning_back_after_a_cube_edge_plays_back() {
+        let mut level = Level::cube(4);
+        level.obstacles = ObstacleSchedule::Off;
+        level.start = GridPos::new(5, 2);
+        level.direction = Direction::Left;
+        let mut replay = Replay::new(3, level, 1, 0);
+        let (mut sim, mut rng) = start_game(&replay.level, 3, 1, 0);
+        let mut turned = false;
+        while sim.ticks() < 6 {
+            // the edge turned the snake, steer back to the heading it had
+            let after_step = sim.direction();
+            if !turned && after_step != Direction::Left {
+                turned = sim.turn(Direction::Left);
+            }
+            replay.record_direction(sim.ticks(), 0, after_step, sim.direction());
+            sim.tick(&mut rng);
+        }
+        assert!(turned && sim.is_alive());
+        replay.finish(&sim);
+        assert_eq!(replay.run().segments(), sim.segments());
+    }
+
+    #[test]
+    fn rival_turns_play_back() {
+        // belts across the arena turn the snakes between their own turns
+        let mut level = Level::open(12, 12);
+        level.obstacles = ObstacleSchedule::Off;
+        level.conveyors = (2..10).map(|x| Conveyor { at: GridPos::new(x, 3), direction: Direction::Right }).collect();
+        let mut replay = Replay::new(5, level, 2, 1);
+        let (mut sim, mut rng) = start_game(&replay.level, 5, 1, 1);
+        while sim.snake(1).is_alive() && sim.ticks() < 100 {
+            let after_step: Vec<Direction> = sim.snakes().iter().map(|snake| snake.direction()).collect();
+            let direction = choose_direction(&sim, 0, Difficulty::Survival);
+            sim.turn(direction);
+            let direction = choose_direction(&sim, 1, Difficulty::Pathfinding);
+            sim.turn_snake(1, direction);
+            for (index, snake) in sim.snakes().iter().enumerate() {
+                replay.record_direction(sim.ticks(), index, after_step[index], snake.direction());
+            }
+            sim.tick(&mut rng);
+        }
+        assert!(!replay.snake_turns.is_empty());
+        replay.finish(&sim);
+        let played = replay.run();
+        for index in 0..2 {
+            assert_eq!(played.snake(index).segments(), sim.snake(index).segments(), "snake {index}");
+        }
+    }
+
+    #[test]
+    fn changed_replays_fail_to_verify() {
+        let mut replay = record(Level::open(12, 12), 7, 200);
+        replay.final_score += 1;
+        assert!(!replay.verify());
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let mut replay = record(Level::open(12, 12), 7, 10);
+        replay.version = REPLAY_VERSION + 1;
+        let text = replay.to_ron().unwrap();
+        assert!(matches!(Replay::from_ron(&text), Err(ReplayError::Version(v)) if v == replay.version));
+    }
+}
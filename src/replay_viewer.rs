@@ -0,0 +1,134 @@
+use bevy::prelude::*;
+use snake_3d::{replay::Replay, rng::GameRng, sim::{Direction, SnakeSim}};
+
+use crate::{
+    arg_value,
+    game_flow::start_run,
+    input::{Action, ActionState},
+    player::{apply_queued_turn, SnakeStep},
+    rivals::steer_rivals,
+    utils::unix_now,
+    GameState, PauseState, Playing,
+};
+
+pub struct ReplayViewerPlugin;
+impl Plugin for ReplayViewerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(LastReplay(load_replay_from_args()))
+            .add_systems(OnEnter(Playing), start_recording
+                .after(start_run)
+                .run_if(in_state(GameState::InGame)))
+            .add_systems(FixedUpdate, (
+                record_direction
+                    .after(apply_queued_turn)
+                    .after(steer_rivals)
+                    .run_if(resource_exists::<Recording>),
+                apply_playback_turns.run_if(in_state(GameState::Replay)),
+            ).before(SnakeStep))
+            .add_systems(FixedUpdate, note_headings
+                .after(SnakeStep)
+                .run_if(resource_exists::<Recording>))
+            .add_systems(Update, stop_playback
+                .run_if(in_state(GameState::Replay))
+                .run_if(in_state(PauseState::Running)))
+            .add_systems(OnEnter(GameState::GameOver), finish_recording)
+            .add_systems(OnEnter(GameState::Menu), |mut commands: Commands| {
+                commands.remove_resource::<Playback>();
+                // left unfinished when a game is quit
+                commands.remove_resource::<Recording>();
+            });
+    }
+}
+
+/// Input of the game being played live, and where each snake was headed
+/// after the last step
+#[derive(Resource)]
+struct Recording(Replay, Vec<Direction>);
+
+/// The replay watched in `GameState::Replay`
+#[derive(Resource)]
+pub struct Playback(pub Replay);
+
+/// Most recent replay, recorded or loaded with `--replay <path>`
+#[derive(Resource, Default)]
+pub struct LastReplay(pub Option<Replay>);
+
+fn load_replay_from_args() -> Option<Replay> {
+    let path = arg_value("--replay")?;
+    match Replay::load(&path) {
+        Ok(replay) => Some(replay),
+        Err(err) => {
+            warn!("could not load {path}: {err}");
+            None
+        }
+    }
+}
+
+fn start_recording(
+    mut commands:   Commands,
+    sim:            Res<SnakeSim>,
+    rng:            Res<GameRng>,
+) {
+    let replay = Replay::new(rng.seed(), sim.level().clone(), sim.snakes().len(), sim.snakes().len() - sim.players());
+    commands.insert_resource(Recording(replay, headings(&sim)));
+}
+
+fn record_direction(
+    sim:            Res<SnakeSim>,
+    mut recording:  ResMut<Recording>,
+) {
+    let Recording(replay, after_step) = recording.as_mut();
+    for (index, snake) in sim.snakes().iter().enumerate() {
+        replay.record_direction(sim.ticks(), index, after_step[index], snake.direction());
+    }
+}
+
+fn note_headings(
+    sim:            Res<SnakeSim>,
+    mut recording:  ResMut<Recording>,
+) {
+    recording.1 = headings(&sim);
+}
+
+fn headings(sim: &SnakeSim) -> Vec<Direction> {
+    sim.snakes().iter().map(|snake| snake.direction()).collect()
+}
+
+fn apply_playback_turns(
+    playback:   Res<Playback>,
+    mut sim:    ResMut<SnakeSim>,
+) {
+    let tick = sim.ticks();
+    playback.0.apply_turns(tick, &mut sim);
+}
+
+/// `Back` ends the replay early, unless it is also the key that pauses
+fn stop_playback(
+    actions:        Res<ActionState>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if actions.just_pressed(Action::Back) && !actions.just_pressed(Action::Pause) {
+        next_state.set(GameState::GameOver);
+    }
+}
+
+fn finish_recording(
+    mut commands:   Commands,
+    recording:      Option<ResMut<Recording>>,
+    sim:            Res<SnakeSim>,
+    mut last:       ResMut<LastReplay>,
+) {
+    let Some(mut recording) = recording else { return };
+    recording.0.finish(&sim);
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = format!("replays/{}-{}.ron", unix_now(), recording.0.seed);
+        match recording.0.save(&path) {
+            Ok(()) => info!("replay saved to {path}"),
+            Err(err) => warn!("{err}"),
+        }
+    }
+    last.0 = Some(recording.0.clone());
+    commands.remove_resource::<Recording>();
+}
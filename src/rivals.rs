@@ -0,0 +1,112 @@
+use bevy::prelude::*;
+use snake_3d::{ai::{choose_direction, Difficulty}, sim::SnakeSim};
+
+use crate::{arg_value, player::{apply_queued_turn, Snake, SnakeBody, SnakeStep}, GameState, GlobalAssets, Playing};
+
+pub struct RivalsPlugin;
+impl Plugin for RivalsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(RivalConfig::from_env())
+            .add_systems(FixedUpdate, steer_rivals
+                .after(apply_queued_turn)
+                .before(SnakeStep)
+                .run_if(in_state(GameState::InGame)))
+            .add_systems(Update, remove_dead_rivals.run_if(in_state(Playing)));
+    }
+}
+
+/// Computer snakes sharing the arena with the players, set at launch with
+/// `--rivals <n>` or `SNAKE_RIVALS`, and how they play with
+/// `--ai <greedy|pathfinding|survival>` or `SNAKE_AI`
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct RivalConfig {
+    pub count: usize,
+    pub difficulty: Difficulty,
+}
+impl RivalConfig {
+    /// More would crowd the default arena
+    const MAX_RIVALS: usize = 6;
+
+    fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(value) = arg_value("--rivals").or_else(|| std::env::var("SNAKE_RIVALS").ok()) {
+            match value.trim().parse() {
+                Ok(count) if count <= Self::MAX_RIVALS => config.count = count,
+                _ => warn!("invalid rival count {value:?}, expected at most {}", Self::MAX_RIVALS),
+            }
+        }
+        if let Some(value) = arg_value("--ai").or_else(|| std::env::var("SNAKE_AI").ok()) {
+            match value.parse() {
+                Ok(difficulty) => config.difficulty = difficulty,
+                Err(err) => warn!("{err}"),
+            }
+        }
+        config
+    }
+
+    /// One more rival on the menu, none again after the most
+    pub fn next_count(&mut self) {
+        self.count = (self.count + 1) % (Self::MAX_RIVALS + 1);
+    }
+
+    /// The way of playing after this one on the menu
+    pub fn next_difficulty(&mut self) {
+        let all = Difficulty::ALL;
+        let next = all.iter().position(|&d| d == self.difficulty).map_or(0, |i| (i + 1) % all.len());
+        self.difficulty = all[next];
+    }
+
+    pub fn count_label(&self) -> String {
+        match self.count {
+            0 => "No rivals".to_string(),
+            1 => "1 rival".to_string(),
+            count => format!("{count} rivals"),
+        }
+    }
+
+    pub fn difficulty_label(&self) -> String {
+        match self.difficulty {
+            Difficulty::Greedy => "Greedy",
+            Difficulty::Pathfinding => "Pathfinding",
+            Difficulty::Survival => "Survival",
+        }.to_string()
+    }
+}
+
+/// Pick every living rival's heading right before the grid step, replays
+/// have them recorded instead
+pub fn steer_rivals(
+    config:     Res<RivalConfig>,
+    mut sim:    ResMut<SnakeSim>,
+) {
+    for index in sim.players()..sim.snakes().len() {
+        if !sim.snake(index).is_alive() {
+            continue;
+        }
+        let direction = choose_direction(&sim, index, config.difficulty);
+        sim.turn_snake(index, direction);
+    }
+}
+
+/// A rival that died leaves the arena, the game goes on
+fn remove_dead_rivals(
+    mut commands:   Commands,
+    game_assets:    Res<GlobalAssets>,
+    sim:            Res<SnakeSim>,
+    rivals:         Query<(Entity, &Snake), Without<SnakeBody>>,
+) {
+    for (entity, snake) in rivals.iter() {
+        if snake.index < sim.players() || sim.snake(snake.index).is_alive() {
+            continue;
+        }
+        for &body in snake.bodies.iter() {
+            commands.entity(body).despawn_recursive();
+        }
+        commands.entity(entity).despawn_recursive();
+        commands.spawn((
+            AudioPlayer::<AudioSource>(game_assets.dead.clone()),
+            PlaybackSettings::DESPAWN,
+        ));
+    }
+}
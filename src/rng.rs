@@ -0,0 +1,71 @@
+//! Seeded randomness shared by every gameplay system.
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, RngCore, SeedableRng};
+
+/// All gameplay randomness is drawn from here so a run can be replayed from its seed.
+#[derive(Resource, Debug, Clone)]
+pub struct GameRng {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    /// Seeded from entropy, the seed can still be read back with `seed()`
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+pub trait RandomChooser<T> {
+    fn choose_random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<T>;
+    fn choose_random_n<R: Rng + ?Sized>(&self, n: usize, rng: &mut R) -> Vec<T>;
+}
+impl<T: Clone> RandomChooser<T> for Vec<T> {
+    fn choose_random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        
+        let index = rng.gen_range(0..self.len());
+        Some(self[index].clone()) 
+    }
+
+    fn choose_random_n<R: Rng + ?Sized>(&self, n: usize, rng: &mut R) -> Vec<T> {
+        let mut indices: Vec<usize> = (0..self.len()).collect();
+        indices.shuffle(rng);
+        
+        let count = n.min(self.len());
+        indices.truncate(count);
+        
+        indices.iter()
+               .map(|&i| self[i].clone())
+               .collect()
+    }
+}
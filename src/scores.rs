@@ -0,0 +1,127 @@
+//! Best finished games, kept in a small RON file between sessions.
+
+use std::{fs, io, path::{Path, PathBuf}};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{ai::Difficulty, level::Level};
+
+/// Entries kept in the table
+pub const MAX_SCORES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreEntry {
+    pub score: i32,
+    /// Game time in seconds
+    pub time: f32,
+    /// Unix seconds when the game ended
+    pub date: u64,
+    pub mode: String,
+    pub seed: u64,
+}
+
+/// What a game was played on, only games of the same mode compare: the
+/// arena and its size, wrapping, the food count and the rivals
+pub fn game_mode(level: &Level, rivals: usize, difficulty: Difficulty) -> String {
+    let (width, height) = (level.width, level.height);
+    let plain = Level { wrap: false, food_count: 1, ..level.clone() };
+    let mut mode = if plain == Level::open(width, height) {
+        format!("classic {width}x{height}")
+    } else if plain == Level::cube(width / 4) {
+        format!("cube {}", width / 4)
+    } else if !level.name.is_empty() {
+        format!("{} {width}x{height}", level.name)
+    } else {
+        format!("custom {width}x{height}")
+    };
+    if level.wrap {
+        mode.push_str(" wrap");
+    }
+    if level.food_count != 1 {
+        mode.push_str(&format!(" {} foods", level.food_count));
+    }
+    if rivals > 0 {
+        mode.push_str(&format!(" +{rivals} {difficulty}"));
+    }
+    mode
+}
+
+/// Best scores first, equal scores ordered by the shorter game
+#[derive(Resource, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HighScores {
+    entries: Vec<ScoreEntry>,
+}
+
+impl HighScores {
+    pub fn entries(&self) -> &[ScoreEntry] {
+        &self.entries
+    }
+
+    /// Insert a finished game, returns its rank if it made the table
+    pub fn add(&mut self, entry: ScoreEntry) -> Option<usize> {
+        let rank = self.entries
+            .iter()
+            .position(|e| entry.score > e.score || (entry.score == e.score && entry.time < e.time))
+            .unwrap_or(self.entries.len());
+        if rank >= MAX_SCORES {
+            return None;
+        }
+        self.entries.insert(rank, entry);
+        self.entries.truncate(MAX_SCORES);
+        Some(rank)
+    }
+
+    /// `highscores.ron` in the platform data directory
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("snake_3d").join("highscores.ron"))
+    }
+
+    /// A missing file is an empty table
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => ron::from_str(&text).map_err(io::Error::other),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate::{generate_level, Layout}, grid::GridPos, rng::GameRng};
+
+    #[test]
+    fn games_on_other_arenas_have_their_own_mode() {
+        let classic = game_mode(&Level::open(25, 25), 0, Difficulty::default());
+        assert_eq!(classic, "classic 25x25");
+
+        let mut wrap = Level::open(25, 25);
+        wrap.wrap = true;
+        let mut walls = Level::open(25, 25);
+        walls.walls.push(GridPos::new(1, 1));
+        let others = [
+            game_mode(&Level::open(40, 25), 0, Difficulty::default()),
+            game_mode(&wrap, 0, Difficulty::default()),
+            game_mode(&walls, 0, Difficulty::default()),
+            game_mode(&Level::cube(10), 0, Difficulty::default()),
+            game_mode(&generate_level(Layout::Maze, 25, 25, &mut GameRng::new(1)), 0, Difficulty::default()),
+            game_mode(&Level::open(25, 25), 2, Difficulty::Greedy),
+        ];
+        for (i, mode) in others.iter().enumerate() {
+            assert_ne!(*mode, classic);
+            assert!(others[..i].iter().all(|other| other != mode), "{mode}");
+        }
+        assert_eq!(others[5], "classic 25x25 +2 greedy");
+    }
+}
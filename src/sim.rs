@@ -0,0 +1,1014 @@
+//! Renderer-free snake rules.
+//!
+//! `SnakeSim` owns the logical state of a game (grid cells, snake segments,
+//! food, obstacles, score) and advances it one grid step at a time. The Bevy
+//! plugins drive it and mirror its state onto meshes, but it can run on its own.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    food::{Effect, FoodItem, FoodKind, BOMB_RADIUS, FAST_FACTOR, SHRINK_LENGTH, SLOW_FACTOR},
+    generate::{keep_connected, layout_walls},
+    grid::GridPos,
+    level::{Level, ObstacleSchedule},
+    rng::RandomChooser,
+};
+
+/// Grid steps per second at the start of a game
+pub const BASE_SPEED: f32 = 3.0;
+/// Body lengths at which the snake gains one step per second
+pub const BOOST_SPEED_AT: [usize; 5] = [
+    5, 10, 20, 30, 40
+];
+/// Seconds between two random map changes
+pub const MAP_CHANGE_INTERVAL: f32 = 5.0;
+/// Seconds a cube flashes before it starts moving
+pub const OBSTACLE_WARN_TIME: f32 = 1.5;
+/// Seconds a cube takes to rise or sink
+pub const OBSTACLE_MOVE_TIME: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction { Up, Down, Left, Right }
+impl Direction {
+    pub const ALL: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+    /// Grid offset of one step
+    pub fn offset(&self) -> GridPos {
+        match self {
+            Direction::Up => GridPos::new(0, -1),
+            Direction::Down => GridPos::new(0, 1),
+            Direction::Left => GridPos::new(-1, 0),
+            Direction::Right => GridPos::new(1, 0),
+        }
+    }
+
+    pub fn norm(&self) -> Vec3 {
+        self.offset().to_world()
+    }
+
+    /// The next heading turning right
+    pub fn clockwise(&self) -> Self {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellKind {
+    Floor,
+    /// Floor that will become a wall, seconds left. Still walkable.
+    Rising(f32),
+    Wall,
+    /// Wall that will become floor again, seconds left
+    Sinking(f32),
+    /// Wall placed by the level, it never moves
+    Solid,
+    /// No floor at all
+    Hole,
+    /// One end of a portal, walked through but never raised or given food
+    Portal,
+    /// Floor that a patrol or sweeper arm passes over, blocked while it is there
+    Track,
+    /// Floor that pushes the snake along
+    Conveyor(Direction),
+}
+impl CellKind {
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, CellKind::Wall | CellKind::Sinking(_) | CellKind::Solid | CellKind::Hole)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeathCause {
+    OutOfBounds,
+    Obstacle,
+    SelfCollision,
+    /// Two heads ran into each other, or swapped cells
+    HeadOn,
+    /// Ran into another snake's body
+    HitSnake,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimEvent {
+    Moved { snake: usize, from: GridPos, to: GridPos },
+    /// The step of a `Moved` went over a wrapping edge and came in on the far side
+    Wrapped { snake: usize },
+    AteFood { snake: usize, pos: GridPos, kind: FoodKind },
+    FoodSpawned(GridPos, FoodKind),
+    /// A food ran out of time, a new one is spawned in its place
+    FoodExpired(GridPos),
+    /// Segments taken off the tail
+    Shrunk { snake: usize, count: usize },
+    EffectStarted { snake: usize, effect: Effect },
+    EffectEnded { snake: usize, effect: Effect },
+    SpeedBoost(f32),
+    /// Cells that started warning and will rise into walls
+    ObstaclesRaised(Vec<GridPos>),
+    /// Walls that started sinking back into floor
+    ObstaclesLowered(Vec<GridPos>),
+    /// Cells that were warning and stay floor after all
+    ObstaclesCleared(Vec<GridPos>),
+    Died { snake: usize, cause: DeathCause },
+}
+
+/// One snake in the arena. Players come first, then the computer's rivals.
+#[derive(Debug, Clone)]
+pub struct SnakeState {
+    /// Head first
+    segments: VecDeque<GridPos>,
+    direction: Direction,
+    /// Timed effects and the seconds they have left
+    effects: Vec<(Effect, f32)>,
+    score: i32,
+    alive: bool,
+}
+
+impl SnakeState {
+    fn new(start: GridPos, direction: Direction) -> Self {
+        Self {
+            segments: VecDeque::from([start]),
+            direction,
+            effects: Vec::new(),
+            score: 0,
+            alive: true,
+        }
+    }
+
+    pub fn head(&self) -> GridPos {
+        self.segments[0]
+    }
+
+    /// All segments, head first
+    pub fn segments(&self) -> &VecDeque<GridPos> { &self.segments }
+    pub fn direction(&self) -> Direction { self.direction }
+    pub fn effects(&self) -> &[(Effect, f32)] { &self.effects }
+    pub fn score(&self) -> i32 { self.score }
+    pub fn is_alive(&self) -> bool { self.alive }
+
+    pub fn has_effect(&self, effect: Effect) -> bool {
+        self.effects.iter().any(|&(e, _)| e == effect)
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct SnakeSim {
+    level: Level,
+    cells: Vec<CellKind>,
+    snakes: Vec<SnakeState>,
+    /// The first snakes are players, the game ends when one of them dies.
+    /// The others are rivals and drop out on their own.
+    players: usize,
+    foods: Vec<FoodItem>,
+    /// Steps per second before effects
+    speed: f32,
+    ticks: u64,
+    time_elapsed: f32,
+    /// Countdown to the next random map change
+    map_change_in: f32,
+    /// Index of the next scripted obstacle wave
+    next_wave: usize,
+}
+
+impl SnakeSim {
+    /// Set up the level's walls and holes, without any food yet
+    pub fn new(level: &Level) -> Self {
+        Self::with_snakes(level, 1, 0)
+    }
+
+    /// Like `new` with `players` snakes followed by `rivals`, placed by `Level::starts`
+    pub fn with_snakes(level: &Level, players: usize, rivals: usize) -> Self {
+        let map_change_in = match level.obstacles {
+            ObstacleSchedule::Random { interval } | ObstacleSchedule::Generated { interval, .. } => interval,
+            _ => MAP_CHANGE_INTERVAL,
+        };
+        let mut sim = Self {
+            level: level.clone(),
+            cells: vec![CellKind::Floor; level.width * level.height],
+            snakes: level.starts(players + rivals).into_iter()
+                .map(|(start, direction)| SnakeState::new(start, direction))
+                .collect(),
+            players,
+            foods: Vec::new(),
+            speed: BASE_SPEED,
+            ticks: 0,
+            time_elapsed: 0.0,
+            map_change_in,
+            next_wave: 0,
+        };
+        for &pos in level.walls.iter() {
+            sim.set_cell(pos, CellKind::Solid);
+        }
+        for &pos in level.holes.iter() {
+            sim.set_cell(pos, CellKind::Hole);
+        }
+        for &(a, b) in level.portals.iter() {
+            sim.set_cell(a, CellKind::Portal);
+            sim.set_cell(b, CellKind::Portal);
+        }
+        for pos in level.tracks() {
+            sim.set_cell(pos, CellKind::Track);
+        }
+        for conveyor in level.conveyors.iter() {
+            sim.set_cell(conveyor.at, CellKind::Conveyor(conveyor.direction));
+        }
+        // the net of a cube has no floor around its faces, the stairs have no ceiling
+        for i in 0..sim.cells.len() {
+            let pos = GridPos::new((i / level.height) as i32, (i % level.height) as i32);
+            if !level.contains(pos) || level.over_ramp(pos) {
+                sim.cells[i] = CellKind::Hole;
+            }
+        }
+        sim
+    }
+
+    pub fn level(&self) -> &Level { &self.level }
+    pub fn width(&self) -> usize { self.level.width }
+    pub fn height(&self) -> usize { self.level.height }
+    pub fn score(&self) -> i32 { self.snakes[0].score }
+    /// Steps per second, with any slow or fast effect applied. All snakes step
+    /// together, so one slowed down slows them all.
+    pub fn speed(&self) -> f32 {
+        let any = |effect| self.snakes.iter().any(|snake| snake.alive && snake.has_effect(effect));
+        if any(Effect::Slow) {
+            self.speed * SLOW_FACTOR
+        } else if any(Effect::Fast) {
+            self.speed * FAST_FACTOR
+        } else {
+            self.speed
+        }
+    }
+    pub fn ticks(&self) -> u64 { self.ticks }
+    /// Game time in seconds, each step lasts `1 / speed`
+    pub fn time_elapsed(&self) -> f32 { self.time_elapsed }
+    /// The game goes on while every player lives
+    pub fn is_alive(&self) -> bool { self.snakes[..self.players].iter().all(|snake| snake.alive) }
+    pub fn players(&self) -> usize { self.players }
+    pub fn direction(&self) -> Direction { self.snakes[0].direction }
+    pub fn foods(&self) -> &[FoodItem] { &self.foods }
+    pub fn effects(&self) -> &[(Effect, f32)] { &self.snakes[0].effects }
+    pub fn snakes(&self) -> &[SnakeState] { &self.snakes }
+    pub fn snake(&self, index: usize) -> &SnakeState { &self.snakes[index] }
+
+    pub fn has_effect(&self, effect: Effect) -> bool {
+        self.snakes[0].has_effect(effect)
+    }
+
+    pub fn food_at(&self, pos: GridPos) -> Option<&FoodItem> {
+        self.foods.iter().find(|food| food.pos == pos)
+    }
+
+    pub fn head(&self) -> GridPos {
+        self.snakes[0].head()
+    }
+
+    /// All segments of the first snake, head first
+    pub fn segments(&self) -> &VecDeque<GridPos> {
+        self.snakes[0].segments()
+    }
+
+    pub fn in_bounds(&self, pos: GridPos) -> bool {
+        pos.in_bounds(self.width(), self.height())
+    }
+
+    /// The cell one step from `pos` and the heading there, see `Level::step`
+    pub fn step(&self, pos: GridPos, direction: Direction) -> Option<(GridPos, Direction)> {
+        self.level.step(pos, direction)
+    }
+
+    fn index(&self, pos: GridPos) -> Option<usize> {
+        self.in_bounds(pos).then(|| pos.x as usize * self.height() + pos.z as usize)
+    }
+
+    /// Out of bounds cells read as walls
+    pub fn cell(&self, pos: GridPos) -> CellKind {
+        self.index(pos).map_or(CellKind::Wall, |i| self.cells[i])
+    }
+
+    pub fn set_cell(&mut self, pos: GridPos, kind: CellKind) {
+        if let Some(i) = self.index(pos) {
+            self.cells[i] = kind;
+        }
+    }
+
+    /// Cells under the patrols, then under each sweeper from the pivot out,
+    /// always in the same order and number
+    pub fn hazards(&self) -> Vec<GridPos> {
+        self.hazards_at(self.time_elapsed)
+    }
+
+    /// Every cell a moving obstacle covers during the `steps`-th step from
+    /// now at the current speed, counting from 1 for the next step, see
+    /// `hazards_between`. Nothing for 0.
+    pub fn hazards_during(&self, steps: u32) -> Vec<GridPos> {
+        let Some(before) = steps.checked_sub(1) else { return Vec::new() };
+        let speed = self.speed();
+        self.hazards_between(self.time_elapsed + before as f32 / speed, self.time_elapsed + steps as f32 / speed)
+    }
+
+    fn hazards_at(&self, time: f32) -> Vec<GridPos> {
+        let steps = |interval: f32| (time / interval) as usize;
+        let patrols = self.level.patrols.iter().map(|patrol| patrol.cell(steps(patrol.interval)));
+        let sweepers = self.level.sweepers.iter().flat_map(|sweeper| sweeper.cells(steps(sweeper.interval)));
+        patrols.chain(sweepers).collect()
+    }
+
+    /// Every cell a moving obstacle covers at some point from `start` to
+    /// `end`: where it was, where it is and every cell in between, so a
+    /// block and a snake can't swap places and an arm can't turn past one
+    fn hazards_between(&self, start: f32, end: f32) -> Vec<GridPos> {
+        let steps = |time: f32, interval: f32| (time / interval) as usize;
+        let mut cells = Vec::new();
+        for patrol in self.level.patrols.iter() {
+            let (first, last) = (steps(start, patrol.interval), steps(end, patrol.interval));
+            cells.extend((first..=last).take(2 * patrol.path.len()).map(|s| patrol.cell(s)));
+        }
+        for sweeper in self.level.sweepers.iter() {
+            let (first, last) = (steps(start, sweeper.interval), steps(end, sweeper.interval));
+            cells.extend(sweeper.cells(first));
+            cells.extend((first..last).take(8).flat_map(|s| sweeper.swept(s)));
+        }
+        cells
+    }
+
+    /// Whether a cell is closed right now, by the grid or a moving obstacle
+    pub fn is_blocked(&self, pos: GridPos) -> bool {
+        self.cell(pos).is_blocking() || self.hazards().contains(&pos)
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (GridPos, CellKind)> + '_ {
+        let height = self.height() as i32;
+        self.cells.iter().enumerate().map(move |(i, &kind)| {
+            (GridPos::new(i as i32 / height, i as i32 % height), kind)
+        })
+    }
+
+    /// Change heading, reversing into the body is refused
+    pub fn turn(&mut self, direction: Direction) -> bool {
+        self.turn_snake(0, direction)
+    }
+
+    /// Set the heading without the reversal check, used to restore recorded input
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.set_snake_direction(0, direction);
+    }
+
+    /// `turn` for any snake
+    pub fn turn_snake(&mut self, snake: usize, direction: Direction) -> bool {
+        let snake = &mut self.snakes[snake];
+        if direction == snake.direction.opposite() {
+            return false;
+        }
+        snake.direction = direction;
+        true
+    }
+
+    pub fn set_snake_direction(&mut self, snake: usize, direction: Direction) {
+        self.snakes[snake].direction = direction;
+    }
+
+    /// Place a normal food on a given cell
+    pub fn place_food(&mut self, pos: GridPos) {
+        self.foods.push(FoodItem::new(pos, FoodKind::Normal));
+    }
+
+    /// Place a food of a random kind on a free spawner of the level, or on a
+    /// random free cell when none is
+    pub fn spawn_food<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Option<FoodItem> {
+        let free = self.free_cells();
+        let spawners: Vec<GridPos> = self.level.spawners.iter().copied().filter(|pos| free.contains(pos)).collect();
+        let pos = if spawners.is_empty() { free.choose_random(rng)? } else { spawners.choose_random(rng)? };
+        let food = FoodItem::new(pos, FoodKind::choose(rng));
+        self.foods.push(food);
+        Some(food)
+    }
+
+    /// Advance the game by one grid step, every snake moves at once
+    pub fn tick<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Vec<SimEvent> {
+        let mut events = Vec::new();
+        if !self.is_alive() {
+            return events;
+        }
+        self.ticks += 1;
+        let dt = 1.0 / self.speed();
+        let before = self.time_elapsed;
+        self.time_elapsed += dt;
+        self.update_obstacles(dt, rng, &mut events);
+        self.update_food(dt, rng, &mut events);
+
+        // a belt pushes the snake along, but never back into its own neck
+        for i in 0..self.snakes.len() {
+            if let CellKind::Conveyor(push) = self.cell(self.snakes[i].head()) {
+                let snake = &mut self.snakes[i];
+                if snake.alive && push != snake.direction.opposite() {
+                    snake.direction = push;
+                }
+            }
+        }
+        let steps: Vec<Option<(GridPos, Direction)>> = self.snakes.iter()
+            .map(|snake| self.step(snake.head(), snake.direction))
+            .collect();
+        let grows: Vec<bool> = steps.iter()
+            .map(|step| step.and_then(|(to, _)| self.food_at(to)).is_some_and(|food| food.kind != FoodKind::Shrink))
+            .collect();
+        // the tail moves out of the way unless the snake grows this step,
+        // dead snakes are gone
+        let body = |i: usize| {
+            let snake = &self.snakes[i];
+            let len = snake.segments.len();
+            snake.segments.iter().take(match (snake.alive, grows[i]) {
+                (false, _) => 0,
+                (true, true) => len,
+                (true, false) => len - 1,
+            })
+        };
+
+        // walls rising under the body and moving obstacles running into it kill
+        // too, a ghost only stops at the level's own walls and holes
+        let hazards = self.hazards_between(before, self.time_elapsed);
+        let mut deaths = Vec::new();
+        for (i, snake) in self.snakes.iter().enumerate().filter(|(_, snake)| snake.alive) {
+            let Some((to, _)) = steps[i] else {
+                // the stairs stand in the way like a wall
+                let cause = if self.level.hits_ramp(snake.head(), snake.direction) {
+                    DeathCause::Obstacle
+                } else {
+                    DeathCause::OutOfBounds
+                };
+                deaths.push((i, cause));
+                continue;
+            };
+            let ghost = snake.has_effect(Effect::Ghost);
+            let blocked = |pos: &GridPos| match self.cell(*pos) {
+                CellKind::Solid | CellKind::Hole => true,
+                kind => !ghost && (kind.is_blocking() || hazards.contains(pos)),
+            };
+            // a ghost and the snakes it meets pass through each other
+            let mut others = self.snakes.iter().enumerate()
+                .filter(|&(j, other)| j != i && !ghost && !other.has_effect(Effect::Ghost));
+            let death = if blocked(&to) || snake.segments.iter().any(blocked) {
+                Some(DeathCause::Obstacle)
+            } else if !ghost && body(i).any(|&s| s == to) {
+                Some(DeathCause::SelfCollision)
+            } else if others.clone().any(|(j, other)| other.alive && steps[j].is_some_and(|(their_to, _)| {
+                their_to == to || (their_to == snake.head() && to == other.head())
+            })) {
+                Some(DeathCause::HeadOn)
+            } else if others.any(|(j, _)| body(j).any(|&s| s == to)) {
+                Some(DeathCause::HitSnake)
+            } else {
+                None
+            };
+            if let Some(cause) = death {
+                deaths.push((i, cause));
+            }
+        }
+        for (snake, cause) in deaths {
+            self.snakes[snake].alive = false;
+            events.push(SimEvent::Died { snake, cause });
+        }
+
+        for i in 0..self.snakes.len() {
+            let Some((to, heading)) = steps[i].filter(|_| self.snakes[i].alive) else {
+                continue;
+            };
+            let eaten = self.food_at(to).copied();
+            let snake = &mut self.snakes[i];
+            let from = snake.head();
+            let wrapped = self.level.wraps(from, snake.direction);
+            snake.segments.push_front(to);
+            snake.direction = heading;
+            events.push(SimEvent::Moved { snake: i, from, to });
+            if wrapped {
+                events.push(SimEvent::Wrapped { snake: i });
+            }
+            if !grows[i] {
+                snake.segments.pop_back();
+            }
+            let Some(food) = eaten else {
+                continue;
+            };
+            self.eat(i, food, &mut events);
+            self.refill_food(rng, &mut events);
+            // the longest snake sets the pace
+            let len = self.snakes[i].segments.len();
+            let longest = self.snakes.iter().enumerate()
+                .all(|(j, other)| j == i || !other.alive || other.segments.len() < len);
+            if grows[i] && longest && BOOST_SPEED_AT.contains(&(len - 1)) {
+                self.speed += 1.0;
+                events.push(SimEvent::SpeedBoost(self.speed));
+            }
+        }
+        events
+    }
+
+    /// Score a food a head just moved onto and apply what it does
+    fn eat(&mut self, snake: usize, food: FoodItem, events: &mut Vec<SimEvent>) {
+        self.foods.retain(|f| f.pos != food.pos);
+        self.snakes[snake].score += food.kind.points();
+        events.push(SimEvent::AteFood { snake, pos: food.pos, kind: food.kind });
+        match food.kind {
+            FoodKind::Shrink => {
+                let segments = &mut self.snakes[snake].segments;
+                let count = SHRINK_LENGTH.min(segments.len() - 1);
+                segments.truncate(segments.len() - count);
+                events.push(SimEvent::Shrunk { snake, count });
+            }
+            FoodKind::Bomb => {
+                let head = self.snakes[snake].head();
+                let near = |pos: GridPos| (pos.x - head.x).abs() <= BOMB_RADIUS && (pos.z - head.z).abs() <= BOMB_RADIUS;
+                // walls still warning never come up, raised ones sink
+                let (mut cleared, mut lowered) = (Vec::new(), Vec::new());
+                for (pos, kind) in self.cells().filter(|&(pos, _)| near(pos)) {
+                    match kind {
+                        CellKind::Rising(_) => cleared.push(pos),
+                        CellKind::Wall => lowered.push(pos),
+                        _ => {}
+                    }
+                }
+                for &pos in cleared.iter() {
+                    self.set_cell(pos, CellKind::Floor);
+                }
+                for &pos in lowered.iter() {
+                    self.set_cell(pos, CellKind::Sinking(OBSTACLE_WARN_TIME + OBSTACLE_MOVE_TIME));
+                }
+                events.push(SimEvent::ObstaclesCleared(cleared));
+                events.push(SimEvent::ObstaclesLowered(lowered));
+            }
+            _ => {}
+        }
+        if let Some(effect) = food.kind.effect() {
+            // eating the same kind again starts it over, the opposite one is dropped
+            let effects = &mut self.snakes[snake].effects;
+            effects.retain(|&(e, _)| e != effect && Some(e) != effect.cancels());
+            effects.push((effect, effect.duration()));
+            events.push(SimEvent::EffectStarted { snake, effect });
+        }
+    }
+
+    /// Run down effect and food timers, foods that run out are replaced
+    fn update_food<R: Rng + ?Sized>(&mut self, dt: f32, rng: &mut R, events: &mut Vec<SimEvent>) {
+        for (i, snake) in self.snakes.iter_mut().enumerate() {
+            for &mut (effect, ref mut left) in snake.effects.iter_mut() {
+                *left -= dt;
+                if *left <= 0.0 {
+                    events.push(SimEvent::EffectEnded { snake: i, effect });
+                }
+            }
+            snake.effects.retain(|&(_, left)| left > 0.0);
+        }
+
+        let mut expired = Vec::new();
+        for food in self.foods.iter_mut() {
+            if let Some(left) = food.expires_in.as_mut() {
+                *left -= dt;
+                if *left <= 0.0 {
+                    expired.push(food.pos);
+                }
+            }
+        }
+        for pos in expired {
+            self.foods.retain(|f| f.pos != pos);
+            events.push(SimEvent::FoodExpired(pos));
+            self.refill_food(rng, events);
+        }
+    }
+
+    /// Foods kept out at once, the level's count or its placed foods if there are more
+    pub fn food_count(&self) -> usize {
+        self.level.food_count.max(self.level.foods.len())
+    }
+
+    /// Spawn food until `food_count` are out, or no free cell is left
+    pub fn refill_food<R: Rng + ?Sized>(&mut self, rng: &mut R, events: &mut Vec<SimEvent>) {
+        while self.foods.len() < self.food_count() {
+            let Some(food) = self.spawn_food(rng) else { break };
+            events.push(SimEvent::FoodSpawned(food.pos, food.kind));
+        }
+    }
+
+    fn update_obstacles<R: Rng + ?Sized>(&mut self, dt: f32, rng: &mut R, events: &mut Vec<SimEvent>) {
+        for cell in self.cells.iter_mut() {
+            *cell = match *cell {
+                CellKind::Rising(left) if left <= dt => CellKind::Wall,
+                CellKind::Rising(left) => CellKind::Rising(left - dt),
+                CellKind::Sinking(left) if left <= dt => CellKind::Floor,
+                CellKind::Sinking(left) => CellKind::Sinking(left - dt),
+                kind => kind,
+            };
+        }
+
+        let raised = match &self.level.obstacles {
+            ObstacleSchedule::Random { interval } => {
+                self.map_change_in -= dt;
+                if self.map_change_in > 0.0 {
+                    return;
+                }
+                self.map_change_in += *interval;
+                let cube_count = 10 + ((self.time_elapsed / 20.) as usize).min(25);
+                let cells = self.free_cells().choose_random_n(cube_count, rng);
+                self.safe_to_raise(cells)
+            }
+            ObstacleSchedule::Waves(waves) => {
+                let Some(wave) = waves.get(self.next_wave).filter(|w| w.at <= self.time_elapsed) else {
+                    return;
+                };
+                self.next_wave += 1;
+                self.safe_to_raise(wave.cells.clone())
+            }
+            ObstacleSchedule::Generated { interval, layout } => {
+                self.map_change_in -= dt;
+                if self.map_change_in > 0.0 {
+                    return;
+                }
+                self.map_change_in += *interval;
+                let cells = layout_walls(*layout, self.width(), self.height(), rng);
+                self.safe_to_raise(cells)
+            }
+            ObstacleSchedule::Off => return,
+        };
+
+        // the walls of the previous change go back down
+        let lowered: Vec<GridPos> = self.cells()
+            .filter(|(_, kind)| matches!(kind, CellKind::Rising(_) | CellKind::Wall))
+            .map(|(pos, _)| pos)
+            .collect();
+        for &pos in lowered.iter() {
+            self.set_cell(pos, CellKind::Sinking(OBSTACLE_WARN_TIME + OBSTACLE_MOVE_TIME));
+        }
+        for &pos in raised.iter() {
+            self.set_cell(pos, CellKind::Rising(OBSTACLE_WARN_TIME + OBSTACLE_MOVE_TIME));
+        }
+        events.push(SimEvent::ObstaclesLowered(lowered));
+        events.push(SimEvent::ObstaclesRaised(raised));
+    }
+
+    /// Cells straight ahead of a snake's head that it reaches before a wall
+    /// raised now has finished rising, up to the first blocking cell
+    pub fn projected_path(&self, snake: usize) -> Vec<GridPos> {
+        let snake = &self.snakes[snake];
+        // one extra step in case a speed boost comes in between
+        let steps = (self.speed() * (OBSTACLE_WARN_TIME + OBSTACLE_MOVE_TIME)).ceil() as usize + 1;
+        let mut path = Vec::with_capacity(steps);
+        let (mut pos, mut direction) = (snake.head(), snake.direction);
+        for _ in 0..steps {
+            let Some(next) = self.step(pos, direction) else {
+                break;
+            };
+            (pos, direction) = next;
+            if self.cell(pos).is_blocking() || pos == snake.head() {
+                break;
+            }
+            path.push(pos);
+        }
+        path
+    }
+
+    /// Keep the cells that can turn into walls without catching a snake:
+    /// open floor off the snakes, the food and their projected paths. A raise
+    /// that would cut a head off from any floor, food included, loses the
+    /// walls in the way. The snakes' bodies close off floor like walls, all
+    /// but the tails, which move on.
+    fn safe_to_raise(&self, cells: Vec<GridPos>) -> Vec<GridPos> {
+        let living = || self.snakes.iter().filter(|snake| snake.alive);
+        let paths: Vec<GridPos> = (0..self.snakes.len())
+            .filter(|&i| self.snakes[i].alive)
+            .flat_map(|i| self.projected_path(i))
+            .collect();
+        let mut cells = cells.into_iter()
+            .filter(|pos| self.cell(*pos) == CellKind::Floor
+                && !living().any(|snake| snake.segments.contains(pos))
+                && self.food_at(*pos).is_none()
+                && !paths.contains(pos))
+            .collect();
+        let bodies: Vec<GridPos> = living()
+            .flat_map(|snake| snake.segments.range(..snake.segments.len() - 1))
+            .copied()
+            .collect();
+        // the previous walls sink as these rise, only the level's own stay closed
+        for snake in living() {
+            let head = snake.head();
+            cells = keep_connected(&self.level, head, cells, |pos| {
+                pos != head && (bodies.contains(&pos) || matches!(self.cell(pos), CellKind::Solid | CellKind::Hole))
+            });
+        }
+        cells
+    }
+
+    /// Floor cells where food or new obstacles may appear, away from the snakes and food.
+    pub fn free_cells(&self) -> Vec<GridPos> {
+        // the room kept clear reaches over the edges of a wrapping arena
+        let square = |center: GridPos, size: i32| {
+            let r = size / 2;
+            let layer = self.level.layer_of(center);
+            (-r..=r).flat_map(move |i| (-r..=r).map(move |j| self.level.wrapped(center + GridPos::new(i, j), layer)))
+        };
+        let mut blocked: Vec<GridPos> = Vec::new();
+        // keep more room clear around a fast snake
+        let margin = self.speed().min(10.0) as i32;
+        for snake in self.snakes.iter().filter(|snake| snake.alive) {
+            blocked.extend(snake.segments.iter().copied());
+            blocked.extend(self.step(snake.head(), snake.direction).map(|(next, _)| next));
+            blocked.extend(square(snake.head(), margin));
+        }
+        for food in self.foods.iter() {
+            blocked.extend(square(food.pos, 3));
+        }
+
+        self.cells()
+            .filter(|&(pos, kind)| kind == CellKind::Floor && !blocked.contains(&pos))
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{level::{Patrol, Ramp, Sweeper}, rng::GameRng};
+
+    /// A 10x10 open arena without random obstacles or food, the snake in the
+    /// middle heading up
+    fn quiet_sim() -> SnakeSim {
+        let mut level = Level::open(10, 10);
+        level.obstacles = ObstacleSchedule::Off;
+        level.food_count = 0;
+        SnakeSim::new(&level)
+    }
+
+    fn ahead(sim: &SnakeSim) -> GridPos {
+        sim.head() + sim.direction().offset()
+    }
+
+    /// Eat `count` foods straight ahead
+    fn grow(sim: &mut SnakeSim, rng: &mut GameRng, count: usize) {
+        for _ in 0..count {
+            sim.place_food(ahead(sim));
+            sim.tick(rng);
+        }
+    }
+
+    fn death(events: &[SimEvent]) -> Option<DeathCause> {
+        events.iter().find_map(|event| match *event {
+            SimEvent::Died { cause, .. } => Some(cause),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn tick_moves_the_head() {
+        let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+        let from = sim.head();
+        let to = ahead(&sim);
+        let events = sim.tick(&mut rng);
+        assert_eq!(sim.head(), to);
+        assert_eq!(sim.segments().len(), 1);
+        assert_eq!(sim.ticks(), 1);
+        assert!(events.contains(&SimEvent::Moved { snake: 0, from, to }));
+    }
+
+    #[test]
+    fn eating_grows_and_scores() {
+        let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+        let start = sim.head();
+        let food = ahead(&sim);
+        sim.place_food(food);
+        let events = sim.tick(&mut rng);
+        assert!(events.contains(&SimEvent::AteFood { snake: 0, pos: food, kind: FoodKind::Normal }));
+        assert_eq!(sim.segments(), &VecDeque::from([food, start]));
+        assert_eq!(sim.score(), FoodKind::Normal.points());
+        assert!(sim.foods().is_empty());
+    }
+
+    #[test]
+    fn walls_kill() {
+        for kind in [CellKind::Solid, CellKind::Wall, CellKind::Hole] {
+            let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+            let head = sim.head();
+            sim.set_cell(ahead(&sim), kind);
+            assert_eq!(death(&sim.tick(&mut rng)), Some(DeathCause::Obstacle), "{kind:?}");
+            assert!(!sim.is_alive());
+            assert_eq!(sim.head(), head);
+        }
+    }
+
+    #[test]
+    fn leaving_the_arena_kills() {
+        let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+        let mut events = Vec::new();
+        for _ in 0..sim.height() {
+            events = sim.tick(&mut rng);
+            if !sim.is_alive() {
+                break;
+            }
+        }
+        assert_eq!(death(&events), Some(DeathCause::OutOfBounds));
+        assert_eq!(sim.head().z, 0);
+    }
+
+    #[test]
+    fn the_side_of_a_ramp_is_an_obstacle() {
+        let mut level = Level::open(10, 20);
+        level.obstacles = ObstacleSchedule::Off;
+        level.layers = 2;
+        level.ramps.push(Ramp { at: GridPos::new(5, 5), direction: Direction::Up });
+        level.start = GridPos::new(4, 5);
+        level.direction = Direction::Right;
+        level.validate().unwrap();
+        let (mut sim, mut rng) = (SnakeSim::new(&level), GameRng::new(0));
+        assert_eq!(death(&sim.tick(&mut rng)), Some(DeathCause::Obstacle));
+    }
+
+    #[test]
+    fn patrols_kill_where_they_were_and_where_they_go() {
+        // the block leaves the cell ahead as the head moves in, then it swaps
+        // places with the head
+        for path in [vec![GridPos::new(5, 4), GridPos::new(6, 4)], vec![GridPos::new(5, 4), GridPos::new(5, 5)]] {
+            let mut level = Level::open(10, 10);
+            level.obstacles = ObstacleSchedule::Off;
+            level.patrols.push(Patrol { path: path.clone(), interval: 1.0 / BASE_SPEED });
+            let (mut sim, mut rng) = (SnakeSim::new(&level), GameRng::new(0));
+            assert_eq!(ahead(&sim), path[0]);
+            assert!(sim.hazards_during(0).is_empty());
+            assert!(sim.hazards_during(1).contains(&path[1]));
+            assert_eq!(death(&sim.tick(&mut rng)), Some(DeathCause::Obstacle), "{path:?}");
+        }
+    }
+
+    #[test]
+    fn sweepers_kill_between_their_turns() {
+        // the arm turns from straight up to up right, passing over the cell
+        // ahead on the way without stopping there
+        let mut level = Level::open(10, 10);
+        level.obstacles = ObstacleSchedule::Off;
+        level.start = GridPos::new(5, 2);
+        level.direction = Direction::Down;
+        let sweeper = Sweeper { pivot: GridPos::new(4, 5), length: 2, interval: 1.0 / BASE_SPEED, counter_clockwise: false };
+        level.sweepers.push(sweeper.clone());
+        let (mut sim, mut rng) = (SnakeSim::new(&level), GameRng::new(0));
+        let cell = ahead(&sim);
+        assert!(!sweeper.cells(0).chain(sweeper.cells(1)).any(|pos| pos == cell));
+        assert!(sweeper.swept(0).any(|pos| pos == cell));
+        assert_eq!(death(&sim.tick(&mut rng)), Some(DeathCause::Obstacle));
+    }
+
+    #[test]
+    fn bombs_sink_walls_and_clear_warnings() {
+        let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+        let food = ahead(&sim);
+        let (wall, warning) = (food + GridPos::new(1, 0), food + GridPos::new(-1, 0));
+        sim.set_cell(wall, CellKind::Wall);
+        sim.set_cell(warning, CellKind::Rising(OBSTACLE_WARN_TIME));
+        sim.foods.push(FoodItem::new(food, FoodKind::Bomb));
+        let events = sim.tick(&mut rng);
+        assert!(events.contains(&SimEvent::ObstaclesLowered(vec![wall])));
+        assert!(events.contains(&SimEvent::ObstaclesCleared(vec![warning])));
+        assert_eq!(sim.cell(warning), CellKind::Floor);
+        assert!(matches!(sim.cell(wall), CellKind::Sinking(_)));
+    }
+
+    #[test]
+    fn running_into_the_body_kills() {
+        let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+        grow(&mut sim, &mut rng, 4);
+        let mut events = Vec::new();
+        for direction in [Direction::Right, Direction::Down, Direction::Left] {
+            assert!(sim.turn(direction));
+            events = sim.tick(&mut rng);
+        }
+        assert_eq!(death(&events), Some(DeathCause::SelfCollision));
+    }
+
+    #[test]
+    fn turning_back_is_refused() {
+        let mut sim = quiet_sim();
+        assert!(!sim.turn(Direction::Down));
+        assert_eq!(sim.direction(), Direction::Up);
+    }
+
+    /// A snake of four going round a 2x2 square, the head about to enter the tail's cell
+    fn chasing_tail() -> (SnakeSim, GameRng) {
+        let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+        grow(&mut sim, &mut rng, 3);
+        for direction in [Direction::Right, Direction::Down] {
+            sim.turn(direction);
+            sim.tick(&mut rng);
+        }
+        sim.turn(Direction::Left);
+        assert_eq!(ahead(&sim), *sim.segments().back().unwrap());
+        (sim, rng)
+    }
+
+    #[test]
+    fn the_tail_moves_out_of_the_way() {
+        let (mut sim, mut rng) = chasing_tail();
+        let tail = *sim.segments().back().unwrap();
+        assert_eq!(death(&sim.tick(&mut rng)), None);
+        assert_eq!(sim.head(), tail);
+        assert_eq!(sim.segments().len(), 4);
+    }
+
+    #[test]
+    fn a_growing_tail_stays() {
+        let (mut sim, mut rng) = chasing_tail();
+        sim.place_food(ahead(&sim));
+        assert_eq!(death(&sim.tick(&mut rng)), Some(DeathCause::SelfCollision));
+    }
+
+    #[test]
+    fn raised_walls_never_close_the_head_in_with_its_body() {
+        let mut sim = quiet_sim();
+        // up column 2 from the bottom edge, the tail round the corner at (3, 9)
+        let body = (3..=9).map(|z| GridPos::new(2, z)).chain([GridPos::new(3, 9)]);
+        sim.snakes[0].segments = body.collect();
+        // with the body these would wall the head off from the right side
+        let fence: Vec<GridPos> = (0..=3).map(|z| GridPos::new(3, z)).collect();
+        let raised = sim.safe_to_raise(fence.clone());
+        assert!(raised.len() < fence.len());
+        let segments = sim.segments().clone();
+        let reached = sim.level().flood_fill(sim.head(), |pos| {
+            pos == sim.head() || !raised.contains(&pos) && !segments.range(..segments.len() - 1).any(|&s| s == pos)
+        });
+        assert!(reached[9 * sim.height()]);
+    }
+
+    #[test]
+    fn food_appears_on_free_spawners() {
+        let mut level = Level::open(10, 10);
+        level.obstacles = ObstacleSchedule::Off;
+        let spawners = vec![GridPos::new(1, 1), GridPos::new(8, 1)];
+        level.spawners = spawners.clone();
+        let mut sim = SnakeSim::new(&level);
+        let mut rng = GameRng::new(0);
+        let first = sim.spawn_food(&mut rng).unwrap().pos;
+        assert!(spawners.contains(&first));
+        // the other one is too close to a food too
+        let other = spawners.iter().find(|&&pos| pos != first).unwrap();
+        sim.place_food(*other + Direction::Down.offset());
+        let second = sim.spawn_food(&mut rng).unwrap().pos;
+        assert!(!spawners.contains(&second));
+    }
+
+    #[test]
+    fn crossing_a_wrapping_edge_is_reported() {
+        let mut level = Level::open(10, 10);
+        level.obstacles = ObstacleSchedule::Off;
+        level.food_count = 0;
+        level.wrap = true;
+        level.start = GridPos::new(5, 1);
+        let (mut sim, mut rng) = (SnakeSim::new(&level), GameRng::new(0));
+        assert!(!sim.tick(&mut rng).contains(&SimEvent::Wrapped { snake: 0 }));
+        let events = sim.tick(&mut rng);
+        assert!(events.contains(&SimEvent::Wrapped { snake: 0 }));
+        assert_eq!(sim.head(), GridPos::new(5, 9));
+        assert!(sim.is_alive());
+    }
+
+    #[test]
+    fn free_cells_keep_room_over_a_wrapping_edge() {
+        let mut level = Level::open(10, 10);
+        level.obstacles = ObstacleSchedule::Off;
+        level.food_count = 0;
+        level.wrap = true;
+        level.start = GridPos::new(0, 0);
+        let sim = SnakeSim::new(&level);
+        let free = sim.free_cells();
+        for pos in [GridPos::new(9, 0), GridPos::new(0, 9), GridPos::new(9, 9), GridPos::new(1, 1)] {
+            assert!(!free.contains(&pos), "{pos:?}");
+        }
+        assert!(free.contains(&GridPos::new(2, 2)));
+    }
+
+    #[test]
+    fn free_cells_avoid_the_snake_and_food() {
+        let (mut sim, mut rng) = (quiet_sim(), GameRng::new(0));
+        grow(&mut sim, &mut rng, 3);
+        let food = GridPos::new(1, 8);
+        sim.place_food(food);
+        let free = sim.free_cells();
+        assert!(!free.is_empty());
+        assert!(sim.segments().iter().all(|pos| !free.contains(pos)));
+        assert!(!free.contains(&ahead(&sim)));
+        assert!(!free.contains(&food));
+        // nor right next to the food
+        assert!(!free.contains(&GridPos::new(2, 9)));
+        assert!(free.iter().all(|&pos| sim.cell(pos) == CellKind::Floor));
+    }
+}
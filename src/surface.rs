@@ -0,0 +1,142 @@
+//! The shape the arena is laid on. A cube arena is stored as its unfolded net
+//! so cells keep plain grid coordinates:
+//!
+//! ```text
+//!       [T]
+//!   [L] [F] [R] [B]
+//!       [D]
+//! ```
+//!
+//! Each face is `size` cells square, the net is `4 * size` by `3 * size` and
+//! the cells outside the six faces do not exist.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{grid::GridPos, sim::Direction};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Surface {
+    #[default]
+    Plane,
+    /// The six faces of a cube, crossing an edge leads onto the next face
+    Cube,
+}
+
+/// Where a face sits in the net and how it is folded, in world axes
+struct Face {
+    slot: (i32, i32),
+    /// Outward normal
+    normal: IVec3,
+    /// World direction of `Direction::Right` on the face
+    right: IVec3,
+    /// World direction of `Direction::Down` on the face
+    down: IVec3,
+}
+
+const FACES: [Face; 6] = [
+    // top
+    Face { slot: (1, 0), normal: IVec3::Y, right: IVec3::X, down: IVec3::Z },
+    // left
+    Face { slot: (0, 1), normal: IVec3::NEG_X, right: IVec3::Z, down: IVec3::NEG_Y },
+    // front
+    Face { slot: (1, 1), normal: IVec3::Z, right: IVec3::X, down: IVec3::NEG_Y },
+    // right
+    Face { slot: (2, 1), normal: IVec3::X, right: IVec3::NEG_Z, down: IVec3::NEG_Y },
+    // back
+    Face { slot: (3, 1), normal: IVec3::NEG_Z, right: IVec3::NEG_X, down: IVec3::NEG_Y },
+    // bottom
+    Face { slot: (1, 2), normal: IVec3::NEG_Y, right: IVec3::X, down: IVec3::NEG_Z },
+];
+
+/// Height of the snake above the faces of the cube, the floor cubes sit one lower
+const LAYER_HEIGHT: f32 = 1.0;
+
+/// Geometry of a cube arena with faces of `size` cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CubeNet {
+    size: i32,
+}
+
+impl CubeNet {
+    pub fn new(size: usize) -> Self {
+        Self { size: size as i32 }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Width and height of the net
+    pub fn net_size(&self) -> (usize, usize) {
+        (4 * self.size(), 3 * self.size())
+    }
+
+    fn face(&self, pos: GridPos) -> Option<&Face> {
+        if pos.x < 0 || pos.z < 0 {
+            return None;
+        }
+        let slot = (pos.x / self.size, pos.z / self.size);
+        FACES.iter().find(|face| face.slot == slot)
+    }
+
+    /// Whether a cell of the net lies on one of the faces
+    pub fn contains(&self, pos: GridPos) -> bool {
+        self.face(pos).is_some()
+    }
+
+    /// Cell center on the cube surface, doubled to stay on integers
+    fn point(&self, face: &Face, pos: GridPos) -> IVec3 {
+        let (u, v) = (pos.x - face.slot.0 * self.size, pos.z - face.slot.1 * self.size);
+        face.normal * self.size
+            + face.right * (2 * u + 1 - self.size)
+            + face.down * (2 * v + 1 - self.size)
+    }
+
+    fn world_dir(face: &Face, direction: Direction) -> IVec3 {
+        let offset = direction.offset();
+        face.right * offset.x + face.down * offset.z
+    }
+
+    /// The cell one step from `pos` and the heading there, which changes when
+    /// the step goes over an edge of the cube
+    pub fn step(&self, pos: GridPos, direction: Direction) -> (GridPos, Direction) {
+        let Some(face) = self.face(pos) else {
+            return (pos + direction.offset(), direction);
+        };
+        let next = pos + direction.offset();
+        if self.face(next).is_some_and(|f| f.slot == face.slot) {
+            return (next, direction);
+        }
+        // half a cell to the edge, then half a cell down the next face
+        let heading = Self::world_dir(face, direction);
+        let point = self.point(face, pos) + heading - face.normal;
+        let new_face = FACES.iter().find(|f| f.normal == heading).expect("every direction has a face");
+        let new_heading = -face.normal;
+        let u = (point.dot(new_face.right) + self.size - 1) / 2;
+        let v = (point.dot(new_face.down) + self.size - 1) / 2;
+        let cell = GridPos::new(new_face.slot.0 * self.size + u, new_face.slot.1 * self.size + v);
+        let direction = Direction::ALL
+            .into_iter()
+            .find(|&d| Self::world_dir(new_face, d) == new_heading)
+            .expect("the heading lies on the new face");
+        (cell, direction)
+    }
+
+    /// Where the snake stands on a cell, the cube is centered on the origin
+    pub fn world(&self, pos: GridPos) -> Vec3 {
+        let Some(face) = self.face(pos) else {
+            return pos.to_world();
+        };
+        self.point(face, pos).as_vec3() / 2.0 + face.normal.as_vec3() * LAYER_HEIGHT
+    }
+
+    pub fn normal(&self, pos: GridPos) -> Vec3 {
+        self.face(pos).map_or(Vec3::Y, |face| face.normal.as_vec3())
+    }
+
+    /// World direction a heading points to on the face of a cell
+    pub fn direction(&self, pos: GridPos, direction: Direction) -> Vec3 {
+        self.face(pos).map_or(direction.norm(), |face| Self::world_dir(face, direction).as_vec3())
+    }
+}
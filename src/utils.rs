@@ -1,6 +1,4 @@
-use std::{collections::HashSet, ops::{Add, Mul, Sub}};
-
-use rand::{seq::SliceRandom, thread_rng, Rng};
+use std::ops::{Add, Mul, Sub};
 
 
 
@@ -24,35 +22,6 @@ pub fn format_time(seconds: f32) -> String {
 }
 
 
-pub trait RandomChooser<T> {
-    fn choose_random(&self) -> Option<T>;
-    fn choose_random_n(&self, n: usize) -> Vec<T>;
-}
-impl<T: Clone> RandomChooser<T> for Vec<T> {
-    fn choose_random(&self) -> Option<T> {
-        if self.is_empty() {
-            return None;
-        }
-        
-        let mut rng = thread_rng();
-        let index = rng.gen_range(0..self.len());
-        Some(self[index].clone()) 
-    }
-
-    fn choose_random_n(&self, n: usize) -> Vec<T> {
-        let mut rng = thread_rng();
-        let mut indices: Vec<usize> = (0..self.len()).collect();
-        indices.shuffle(&mut rng);
-        
-        let count = n.min(self.len());
-        indices.truncate(count);
-        
-        indices.iter()
-               .map(|&i| self[i].clone())
-               .collect()
-    }
-}
-
 pub fn create_range(n: f32, m: usize) -> Vec<f32> {
     // calc the step size for the range
     let step = n / (m as f32 - 1.0);
@@ -60,7 +29,31 @@ pub fn create_range(n: f32, m: usize) -> Vec<f32> {
     (0..m).map(|i| i as f32 * step).collect()
 }
 
-pub fn has_common_elements<T: Eq + std::hash::Hash>(vec1: &[T], vec2: &[T]) -> bool {
-    let set: HashSet<_> = vec1.iter().collect();
-    vec2.iter().any(|item| set.contains(item))
-}
\ No newline at end of file
+/// Seconds since the unix epoch, 0 where the clock is unavailable
+pub fn unix_now() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        0
+    }
+}
+
+/// `YYYY-MM-DD` of a unix timestamp, in UTC
+pub fn format_date(unix_secs: u64) -> String {
+    // days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (unix_secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}